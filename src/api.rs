@@ -0,0 +1,564 @@
+use carapax::{
+    methods::{
+        AnswerCallbackQuery, DeleteMessage, EditMessageCaption, EditMessageText, GetChat, GetChatMember,
+        GetUserProfilePhotos, KickChatMember, Method, RestrictChatMember, SendAnimation, SendMessage, SendPhoto,
+        SendSticker,
+    },
+    types::Integer,
+    Api, ExecuteError,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::time::delay_for;
+
+/// Telegram allows about 30 requests per second
+const GLOBAL_LIMIT: Limit = Limit {
+    capacity: 30.0,
+    period: Duration::from_secs(1),
+};
+
+/// Telegram allows about 20 messages per minute in a group
+const CHAT_LIMIT: Limit = Limit {
+    capacity: 20.0,
+    period: Duration::from_secs(60),
+};
+
+/// A request waiting for requests with higher priority checks limits again after this interval
+const PRIORITY_WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum number of attempts to execute a request
+const MAX_ATTEMPTS: u32 = 5;
+
+/// A delay before the second attempt, doubled for each next one
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum delay between attempts, unless telegram asks to wait longer
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Priority of a request, requests with higher priority are executed first when limit is reached
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// Cosmetic requests, e.g. deleting messages
+    Low,
+    /// Questions and other messages
+    Normal,
+    /// Restrictions and bans
+    High,
+}
+
+/// A method executed by `LimitedApi`
+pub trait Request: Method {
+    /// Whether a request can be repeated after a network or server error
+    ///
+    /// Such error does not tell whether telegram executed the request,
+    /// so repeating a message could send it twice.
+    const IS_IDEMPOTENT: bool;
+}
+
+macro_rules! impl_request {
+    ($is_idempotent:expr => $($method:ty),+) => {
+        $(
+            impl Request for $method {
+                const IS_IDEMPOTENT: bool = $is_idempotent;
+            }
+        )+
+    };
+}
+
+impl_request!(true => DeleteMessage, GetChat, GetChatMember, GetUserProfilePhotos, KickChatMember, RestrictChatMember);
+impl_request!(false => AnswerCallbackQuery, EditMessageCaption, EditMessageText, SendAnimation, SendMessage, SendPhoto, SendSticker);
+
+/// A chat and a user affected by a request
+///
+/// Messages sent to a chat are limited per chat, IDs are shown in logs when a request fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Target {
+    chat_id: Option<Integer>,
+    user_id: Option<Integer>,
+    is_message: bool,
+}
+
+impl Target {
+    /// A message sent to a chat
+    pub fn message(chat_id: Integer) -> Self {
+        Self {
+            chat_id: Some(chat_id),
+            user_id: None,
+            is_message: true,
+        }
+    }
+
+    /// A chat itself or its existing messages
+    pub fn chat(chat_id: Integer) -> Self {
+        Self {
+            chat_id: Some(chat_id),
+            user_id: None,
+            is_message: false,
+        }
+    }
+
+    /// A member of a chat
+    pub fn member(chat_id: Integer, user_id: Integer) -> Self {
+        Self {
+            chat_id: Some(chat_id),
+            user_id: Some(user_id),
+            is_message: false,
+        }
+    }
+
+    /// A user outside of a chat, e.g. a private message
+    pub fn user(user_id: Integer) -> Self {
+        Self {
+            chat_id: None,
+            user_id: Some(user_id),
+            is_message: false,
+        }
+    }
+
+    /// Returns a chat where per chat limit is applied
+    fn limited_chat_id(&self) -> Option<Integer> {
+        self.chat_id.filter(|_| self.is_message)
+    }
+}
+
+/// Executes API methods without exceeding telegram limits
+///
+/// All requests share a global limit,
+/// messages sent to a chat are also limited per chat.
+/// Requests failed because of network or server errors are repeated only when they are idempotent.
+/// "Too many requests" errors are always repeated and pause all requests,
+/// other telegram errors are never repeated.
+#[derive(Clone)]
+pub struct LimitedApi {
+    api: Api,
+    limiter: Arc<Mutex<Limiter>>,
+}
+
+impl LimitedApi {
+    pub fn new(api: Api) -> Self {
+        Self {
+            api,
+            limiter: Arc::new(Mutex::new(Limiter::new())),
+        }
+    }
+
+    /// Executes a method with normal priority, only global limit is applied
+    pub async fn execute<M>(&self, method: M) -> Result<M::Response, ExecuteError>
+    where
+        M: Request + Clone,
+        M::Response: DeserializeOwned + Send + 'static,
+    {
+        self.execute_with(Priority::Normal, Target::default(), method).await
+    }
+
+    /// Executes a method with given priority
+    ///
+    /// `target` - a chat and a user affected by the method, per chat limit is applied to messages.
+    pub async fn execute_with<M>(
+        &self,
+        priority: Priority,
+        target: Target,
+        method: M,
+    ) -> Result<M::Response, ExecuteError>
+    where
+        M: Request + Clone,
+        M::Response: DeserializeOwned + Send + 'static,
+    {
+        self.execute_built(priority, target, || async { Ok(method.clone()) })
+            .await
+    }
+
+    /// Executes a method created by `build` for each attempt
+    ///
+    /// Useful for methods which can not be cloned, e.g. uploading a file.
+    pub async fn execute_built<M, E, F, Fut>(
+        &self,
+        priority: Priority,
+        target: Target,
+        mut build: F,
+    ) -> Result<M::Response, E>
+    where
+        M: Request,
+        M::Response: DeserializeOwned + Send + 'static,
+        E: From<ExecuteError>,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<M, E>>,
+    {
+        let mut attempt = 1;
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let method = build().await?;
+            self.acquire(priority, target.limited_chat_id()).await;
+            let err = match self.api.execute(method).await {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+            let delay = match get_retry_delay(&err, backoff, M::IS_IDEMPOTENT) {
+                Some(RetryDelay::Backoff(delay)) => delay,
+                Some(RetryDelay::RetryAfter(delay)) => {
+                    // Flood control applies to the bot, so other requests must wait too
+                    self.lock().pause(Instant::now() + delay);
+                    delay
+                }
+                None => return Err(err.into()),
+            };
+            if attempt == MAX_ATTEMPTS {
+                log::error!(
+                    "Giving up after {} attempt(s) (chat_id={:?}, user_id={:?}): {}",
+                    attempt,
+                    target.chat_id,
+                    target.user_id,
+                    err
+                );
+                return Err(err.into());
+            }
+            log::warn!(
+                "Attempt {} failed, retry in {} second(s): {}",
+                attempt,
+                delay.as_secs_f64(),
+                err
+            );
+            delay_for(delay).await;
+            attempt += 1;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Whether at least a half of the per chat limit is left
+    ///
+    /// Used to skip cosmetic messages, so they do not delay questions.
+    pub fn has_spare_capacity(&self, chat_id: Integer) -> bool {
+        self.lock().has_spare_capacity(chat_id)
+    }
+
+    /// Waits until a request can be sent
+    ///
+    /// A request is marked as waiting only while global limit is exhausted for it,
+    /// so requests waiting for a busy chat do not hold other chats.
+    async fn acquire(&self, priority: Priority, chat_id: Option<Integer>) {
+        let mut waiting = None;
+        loop {
+            let blocked = match self.lock().try_take(priority, chat_id, Instant::now()) {
+                Ok(()) => return,
+                Err(blocked) => blocked,
+            };
+            if blocked.is_global {
+                if waiting.is_none() {
+                    waiting = Some(Waiting::new(self, priority));
+                }
+            } else {
+                waiting = None;
+            }
+            delay_for(blocked.delay).await;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Limiter> {
+        self.limiter.lock().expect("Failed to lock API limiter")
+    }
+}
+
+/// A delay before the next attempt
+#[derive(Debug, PartialEq)]
+enum RetryDelay {
+    /// Request failed because of a network or server error
+    Backoff(Duration),
+    /// Telegram asked to wait
+    RetryAfter(Duration),
+}
+
+/// Returns a delay before the next attempt or None if request must not be repeated
+fn get_retry_delay(err: &ExecuteError, backoff: Duration, is_idempotent: bool) -> Option<RetryDelay> {
+    match err {
+        ExecuteError::Reqwest(_) if is_idempotent => Some(RetryDelay::Backoff(backoff)),
+        ExecuteError::Response(err) => match err.retry_after() {
+            Some(retry_after) => Some(RetryDelay::RetryAfter(Duration::from_secs(retry_after.max(0) as u64))),
+            None if is_idempotent && err.error_code().map_or(false, |code| code >= 500) => {
+                Some(RetryDelay::Backoff(backoff))
+            }
+            None => None,
+        },
+        ExecuteError::Reqwest(_) | ExecuteError::Form(_) | ExecuteError::Json(_) => None,
+    }
+}
+
+/// Why a request can not be sent now
+#[derive(Debug, PartialEq)]
+struct Blocked {
+    /// Time to wait before the next check
+    delay: Duration,
+    /// Whether request waits for global limit rather than per chat one
+    is_global: bool,
+}
+
+/// Marks a request as waiting for global limit while it exists
+struct Waiting<'a> {
+    api: &'a LimitedApi,
+    priority: Priority,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(api: &'a LimitedApi, priority: Priority) -> Self {
+        *api.lock().waiting.entry(priority).or_default() += 1;
+        Self { api, priority }
+    }
+}
+
+impl<'a> Drop for Waiting<'a> {
+    fn drop(&mut self) {
+        let mut limiter = self.api.lock();
+        if let Some(count) = limiter.waiting.get_mut(&self.priority) {
+            *count -= 1;
+        }
+    }
+}
+
+struct Limiter {
+    global: Bucket,
+    chats: HashMap<Integer, Bucket>,
+    /// Number of requests waiting for global limit by priority
+    waiting: HashMap<Priority, usize>,
+    /// Requests are not sent before this time, set by telegram flood control
+    paused_until: Option<Instant>,
+}
+
+impl Limiter {
+    fn new() -> Self {
+        Self {
+            global: Bucket::new(GLOBAL_LIMIT, Instant::now()),
+            chats: HashMap::new(),
+            waiting: HashMap::new(),
+            paused_until: None,
+        }
+    }
+
+    /// Stops sending requests until given time
+    fn pause(&mut self, until: Instant) {
+        if self.paused_until.map_or(true, |x| x < until) {
+            self.paused_until = Some(until);
+        }
+    }
+
+    fn has_spare_capacity(&mut self, chat_id: Integer) -> bool {
+        match self.chats.get_mut(&chat_id) {
+            Some(chat) => {
+                chat.refill(Instant::now());
+                chat.tokens >= chat.capacity / 2.0
+            }
+            None => true,
+        }
+    }
+
+    /// Takes tokens for a request or returns time to wait
+    ///
+    /// Requests with higher priority waiting for global limit are served first.
+    fn try_take(&mut self, priority: Priority, chat_id: Option<Integer>, now: Instant) -> Result<(), Blocked> {
+        self.global.refill(now);
+        for bucket in self.chats.values_mut() {
+            bucket.refill(now);
+        }
+        // Full buckets are not needed anymore
+        self.chats.retain(|_, bucket| !bucket.is_full());
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > now {
+                return Err(Blocked {
+                    delay: paused_until - now,
+                    is_global: true,
+                });
+            }
+            self.paused_until = None;
+        }
+        if let Some(chat_id) = chat_id {
+            let delay = self
+                .chats
+                .entry(chat_id)
+                .or_insert_with(|| Bucket::new(CHAT_LIMIT, now))
+                .delay();
+            if delay > Duration::from_secs(0) {
+                return Err(Blocked {
+                    delay,
+                    is_global: false,
+                });
+            }
+        }
+        let has_higher = self
+            .waiting
+            .iter()
+            .any(|(waiting_priority, count)| *waiting_priority > priority && *count > 0);
+        if has_higher {
+            return Err(Blocked {
+                delay: PRIORITY_WAIT_INTERVAL,
+                is_global: true,
+            });
+        }
+        let delay = self.global.delay();
+        if delay > Duration::from_secs(0) {
+            return Err(Blocked { delay, is_global: true });
+        }
+        self.global.take();
+        if let Some(chat_id) = chat_id {
+            if let Some(chat) = self.chats.get_mut(&chat_id) {
+                chat.take();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A number of requests allowed within a period
+struct Limit {
+    capacity: f64,
+    period: Duration,
+}
+
+/// A token bucket
+struct Bucket {
+    capacity: f64,
+    /// Tokens added per second
+    rate: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            capacity: limit.capacity,
+            rate: limit.capacity / limit.period.as_secs_f64(),
+            tokens: limit.capacity,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+
+    /// Returns time left before a token is available
+    fn delay(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(
+            Limit {
+                capacity: 2.0,
+                period: Duration::from_secs(1),
+            },
+            now,
+        );
+        assert!(bucket.is_full());
+        assert_eq!(bucket.delay(), Duration::from_secs(0));
+        bucket.take();
+        bucket.take();
+        assert_eq!(bucket.delay(), Duration::from_millis(500));
+        bucket.refill(now + Duration::from_millis(250));
+        assert_eq!(bucket.delay(), Duration::from_millis(250));
+        bucket.refill(now + Duration::from_secs(10));
+        assert!(bucket.is_full());
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn global_limit() {
+        let mut limiter = Limiter::new();
+        let now = Instant::now();
+        for _ in 0..30 {
+            assert_eq!(limiter.try_take(Priority::Normal, None, now), Ok(()));
+        }
+        let blocked = limiter.try_take(Priority::High, None, now).unwrap_err();
+        assert!(blocked.is_global);
+        assert!(blocked.delay > Duration::from_secs(0));
+        assert_eq!(
+            limiter.try_take(Priority::High, None, now + Duration::from_secs(1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn chat_limit() {
+        let mut limiter = Limiter::new();
+        let now = Instant::now();
+        for _ in 0..20 {
+            assert_eq!(limiter.try_take(Priority::Normal, Some(1), now), Ok(()));
+        }
+        let blocked = limiter.try_take(Priority::Normal, Some(1), now).unwrap_err();
+        assert!(!blocked.is_global);
+        assert_eq!(blocked.delay, Duration::from_secs(3));
+        assert!(!limiter.has_spare_capacity(1));
+        assert!(limiter.has_spare_capacity(2));
+        assert_eq!(limiter.try_take(Priority::Normal, Some(2), now), Ok(()));
+    }
+
+    #[test]
+    fn priority() {
+        let mut limiter = Limiter::new();
+        let now = Instant::now();
+        limiter.waiting.insert(Priority::High, 1);
+        assert_eq!(
+            limiter.try_take(Priority::Normal, None, now),
+            Err(Blocked {
+                delay: PRIORITY_WAIT_INTERVAL,
+                is_global: true
+            })
+        );
+        assert_eq!(limiter.try_take(Priority::High, None, now), Ok(()));
+        limiter.waiting.insert(Priority::High, 0);
+        assert_eq!(limiter.try_take(Priority::Low, None, now), Ok(()));
+    }
+
+    #[test]
+    fn pause() {
+        let mut limiter = Limiter::new();
+        let now = Instant::now();
+        limiter.pause(now + Duration::from_secs(2));
+        limiter.pause(now + Duration::from_secs(1));
+        assert_eq!(
+            limiter.try_take(Priority::High, None, now),
+            Err(Blocked {
+                delay: Duration::from_secs(2),
+                is_global: true
+            })
+        );
+        assert_eq!(
+            limiter.try_take(Priority::High, None, now + Duration::from_secs(2)),
+            Ok(())
+        );
+        assert_eq!(limiter.paused_until, None);
+    }
+
+    #[test]
+    fn target() {
+        assert_eq!(Target::message(-100).limited_chat_id(), Some(-100));
+        assert_eq!(Target::chat(-100).limited_chat_id(), None);
+        assert_eq!(Target::member(-100, 1).limited_chat_id(), None);
+        assert_eq!(Target::user(1).limited_chat_id(), None);
+        assert_eq!(Target::default().limited_chat_id(), None);
+    }
+}
@@ -1,7 +1,15 @@
 use crate::{
-    config::{Config, ConfigError, WebhookUrl},
+    api::LimitedApi,
+    batch::BatchQueue,
+    blocklist::{Blocklist, BlocklistError},
+    challenge::{ChallengeRegistry, ChallengeState},
+    config::{BlocklistConfig, Config, ConfigError, WebhookUrl},
     context::Context,
-    handler::{on_callback_query, on_message},
+    handler::{execute_job, on_callback_query, on_message},
+    joins::JoinTracker,
+    lockdown::LockdownRegistry,
+    scheduler::{self, Job, Scheduler},
+    store::{Store, StoreError, UploadedFile},
 };
 use carapax::{
     longpoll::LongPoll,
@@ -9,31 +17,76 @@ use carapax::{
     webhook, Api, ApiError, Dispatcher,
 };
 use hyper::Error as HyperError;
-use std::{env, fmt, io::Error as IoError, time::Duration};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    io::Error as IoError,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tempfile::tempdir;
+use tokio::fs;
 
 const SESSION_GC_PERIOD: Duration = Duration::from_secs(86400);
 const SESSION_LIFETIME: Duration = Duration::from_secs(43200);
+const SESSION_DIRECTORY: &str = "sessions";
 
 pub async fn run() -> Result<(), Error> {
     env_logger::init();
     let Config {
         api: api_config,
         webhook_url,
+        data_dir,
+        blocklist,
         chats,
     } = match env::args().nth(1) {
         Some(path) => Config::from_file(path).await?,
         None => return Err(Error::ConfigPathMissing),
     };
-    let session_path = tempdir().map_err(Error::CreateSessionDirectory)?.into_path();
+    let data_dir = match data_dir {
+        Some(data_dir) => data_dir,
+        None => {
+            log::warn!("Data directory is not set, pending questions will be lost on restart");
+            tempdir().map_err(Error::CreateSessionDirectory)?.into_path()
+        }
+    };
+    let session_path = data_dir.join(SESSION_DIRECTORY);
+    fs::create_dir_all(&session_path)
+        .await
+        .map_err(Error::CreateSessionDirectory)?;
+    let store = Store::open(&data_dir).await?;
     let session_backend = FilesystemBackend::new(session_path);
     let session_manager = SessionManager::new(session_backend.clone());
     let api = Api::new(api_config)?;
-    let mut dispatcher = Dispatcher::new(Context {
-        api: api.clone(),
-        chats,
+    let blocklist = match blocklist {
+        Some(BlocklistConfig { path, reload_interval }) => {
+            let blocklist = Blocklist::open(path).await?;
+            tokio::spawn(blocklist.clone().run(reload_interval));
+            Some(blocklist)
+        }
+        None => None,
+    };
+    let challenges = restore_challenges(&store).await?;
+    let uploaded_files = restore_uploaded_files(&store).await;
+    let (scheduler, scheduler_runner) = Scheduler::new(store.clone());
+    let context = Context {
+        api: LimitedApi::new(api.clone()),
+        chats: Arc::new(chats),
         session_manager,
-    });
+        store,
+        scheduler,
+        challenges,
+        blocklist,
+        joins: JoinTracker::default(),
+        lockdowns: LockdownRegistry::default(),
+        batches: BatchQueue::default(),
+        uploaded_files: Arc::new(Mutex::new(uploaded_files)),
+        default_permissions: Arc::new(Mutex::new(HashMap::new())),
+    };
+    let jobs_context = context.clone();
+    tokio::spawn(scheduler_runner.run(move |job| execute_job(jobs_context.clone(), job)));
+    let mut dispatcher = Dispatcher::new(context);
     dispatcher.add_handler(on_message);
     dispatcher.add_handler(on_callback_query);
 
@@ -58,12 +111,56 @@ pub async fn run() -> Result<(), Error> {
     Ok(())
 }
 
+/// Restores states of challenges started before restart
+async fn restore_challenges(store: &Store) -> Result<ChallengeRegistry, StoreError> {
+    let challenges = ChallengeRegistry::default();
+    for job in scheduler::load_jobs(store).await? {
+        match job {
+            Job::SendQuestion(request) => {
+                challenges.start(request.chat_id, request.user.id, ChallengeState::Scheduled);
+            }
+            Job::SendBatch { requests, .. } => {
+                for request in requests {
+                    challenges.start(request.chat_id, request.user.id, ChallengeState::Scheduled);
+                }
+            }
+            _ => {}
+        }
+    }
+    for challenge in store.load_challenges().await? {
+        challenges.start(challenge.chat_id, challenge.user.id, ChallengeState::Asked);
+    }
+    Ok(challenges)
+}
+
+/// Restores media files uploaded before restart, changed files will be uploaded again
+///
+/// Files are just uploaded again when they can not be loaded.
+async fn restore_uploaded_files(store: &Store) -> HashMap<PathBuf, UploadedFile> {
+    let files = match store.load_uploaded_files().await {
+        Ok(files) => files,
+        Err(err) => {
+            log::warn!("Failed to load uploaded files: {}", err);
+            return HashMap::new();
+        }
+    };
+    let mut result = HashMap::new();
+    for file in files {
+        if file.is_actual().await {
+            result.insert(file.path.clone(), file);
+        }
+    }
+    result
+}
+
 #[derive(Debug)]
 pub enum Error {
     Api(ApiError),
+    Blocklist(BlocklistError),
     Config(ConfigError),
     ConfigPathMissing,
     CreateSessionDirectory(IoError),
+    Store(StoreError),
     Webhook(HyperError),
 }
 
@@ -73,20 +170,34 @@ impl From<ApiError> for Error {
     }
 }
 
+impl From<BlocklistError> for Error {
+    fn from(err: BlocklistError) -> Self {
+        Error::Blocklist(err)
+    }
+}
+
 impl From<ConfigError> for Error {
     fn from(err: ConfigError) -> Self {
         Error::Config(err)
     }
 }
 
+impl From<StoreError> for Error {
+    fn from(err: StoreError) -> Self {
+        Error::Store(err)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::Error::*;
         match self {
             Api(err) => write!(out, "{}", err),
+            Blocklist(err) => write!(out, "{}", err),
             Config(err) => write!(out, "{}", err),
             ConfigPathMissing => write!(out, "You need to provide a path to config"),
             CreateSessionDirectory(err) => write!(out, "Failed to create session directory: {}", err),
+            Store(err) => write!(out, "Failed to open store: {}", err),
             Webhook(err) => write!(out, "Webhook error: {}", err),
         }
     }
@@ -0,0 +1,59 @@
+use crate::scheduler::QuestionRequest;
+use carapax::types::Integer;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Collects new chat members to ask them by a single question
+///
+/// Batches are kept in memory only, jobs sending them are saved in store.
+/// Every batch has its own ID, so a batch started after restart
+/// does not replace a job of a batch started before.
+#[derive(Clone, Default)]
+pub struct BatchQueue {
+    chats: Arc<Mutex<HashMap<Integer, Batch>>>,
+}
+
+struct Batch {
+    /// ID of a new chat members message which started the batch
+    id: Integer,
+    send_at: Instant,
+    requests: Vec<QuestionRequest>,
+}
+
+impl BatchQueue {
+    /// Adds requests to a batch of the chat
+    ///
+    /// A new batch is started when chat has no batch, it must be sent after given delay.
+    /// Returns ID of the batch, time left before sending and all requests of the batch.
+    pub fn push(
+        &self,
+        chat_id: Integer,
+        requests: Vec<QuestionRequest>,
+        delay: Duration,
+    ) -> (Integer, Duration, Vec<QuestionRequest>) {
+        let now = Instant::now();
+        let mut chats = self.chats.lock().expect("Failed to lock batches");
+        let batch = chats.entry(chat_id).or_insert_with(|| Batch {
+            id: requests.first().map(|x| x.message_id).unwrap_or_default(),
+            send_at: now + delay,
+            requests: Vec::new(),
+        });
+        batch.requests.extend(requests);
+        (
+            batch.id,
+            batch.send_at.saturating_duration_since(now),
+            batch.requests.clone(),
+        )
+    }
+
+    /// Removes a batch which is about to be sent, so next users start a new one
+    pub fn remove(&self, chat_id: Integer, batch_id: Integer) {
+        let mut chats = self.chats.lock().expect("Failed to lock batches");
+        if chats.get(&chat_id).map(|batch| batch.id) == Some(batch_id) {
+            chats.remove(&chat_id);
+        }
+    }
+}
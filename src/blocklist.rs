@@ -0,0 +1,139 @@
+use carapax::types::Integer;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::Error as IoError,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, time::delay_for};
+
+const DEFAULT_REASON: &str = "listed in blocklist";
+
+/// A list of users to ban on join
+///
+/// Each line of the file contains a user ID and an optional reason separated by comma.
+/// Empty lines and lines started with '#' are ignored.
+#[derive(Clone)]
+pub struct Blocklist {
+    path: PathBuf,
+    state: Arc<RwLock<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    modified: Option<SystemTime>,
+    /// Reasons by user ID
+    users: HashMap<Integer, String>,
+}
+
+impl Blocklist {
+    /// Loads a blocklist from file
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, BlocklistError> {
+        let blocklist = Self {
+            path: path.as_ref().to_owned(),
+            state: Arc::new(RwLock::new(State::default())),
+        };
+        blocklist.reload().await?;
+        Ok(blocklist)
+    }
+
+    /// Returns a reason of the ban if user is listed
+    pub fn find(&self, user_id: Integer) -> Option<String> {
+        self.state
+            .read()
+            .expect("Failed to lock blocklist")
+            .users
+            .get(&user_id)
+            .cloned()
+    }
+
+    /// Reloads blocklist periodically
+    pub async fn run(self, interval: Duration) {
+        loop {
+            delay_for(interval).await;
+            if let Err(err) = self.reload().await {
+                log::error!("Failed to reload blocklist: {}", err);
+            }
+        }
+    }
+
+    /// Reads file again if it was modified since last read
+    async fn reload(&self) -> Result<(), BlocklistError> {
+        let modified = fs::metadata(&self.path)
+            .await
+            .and_then(|x| x.modified())
+            .map_err(BlocklistError::Read)?;
+        if self.state.read().expect("Failed to lock blocklist").modified == Some(modified) {
+            return Ok(());
+        }
+        let data = fs::read_to_string(&self.path).await.map_err(BlocklistError::Read)?;
+        let users = parse(&data);
+        log::info!("Loaded {} user(s) from blocklist", users.len());
+        *self.state.write().expect("Failed to lock blocklist") = State {
+            modified: Some(modified),
+            users,
+        };
+        Ok(())
+    }
+}
+
+/// Returns reasons by user ID, invalid lines are skipped
+fn parse(data: &str) -> HashMap<Integer, String> {
+    let mut users = HashMap::new();
+    for (idx, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, ',');
+        let user_id = parts.next().unwrap_or("");
+        let reason = parts.next().unwrap_or("").trim();
+        match user_id.trim().parse::<Integer>() {
+            Ok(user_id) => {
+                let reason = if reason.is_empty() { DEFAULT_REASON } else { reason };
+                users.insert(user_id, String::from(reason));
+            }
+            Err(err) => log::warn!("Skipping line {} of blocklist: {}", idx + 1, err),
+        }
+    }
+    users
+}
+
+#[derive(Debug)]
+pub enum BlocklistError {
+    Read(IoError),
+}
+
+impl fmt::Display for BlocklistError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlocklistError::Read(err) => write!(out, "can not read blocklist: {}", err),
+        }
+    }
+}
+
+impl Error for BlocklistError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BlocklistError::Read(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let users = parse("# comment\n\n1\n 2 , spam \n3,\nabc,reason\n4,a, b\n");
+        assert_eq!(users.len(), 4);
+        assert_eq!(users[&1], DEFAULT_REASON);
+        assert_eq!(users[&2], "spam");
+        assert_eq!(users[&3], DEFAULT_REASON);
+        assert_eq!(users[&4], "a, b");
+    }
+}
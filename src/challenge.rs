@@ -0,0 +1,226 @@
+use carapax::types::Integer;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A state of a challenge for a new chat member
+///
+/// Scheduled → Asked → Passed, Failed, Expired or Left
+///
+/// Scheduled → Kicked when there are too many pending questions
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChallengeState {
+    /// Question will be sent after ask timeout
+    Scheduled,
+    /// Question is sent, waiting for an answer
+    Asked,
+    /// User pressed the right button
+    Passed,
+    /// User pressed a wrong button
+    Failed,
+    /// User did not answer in time
+    Expired,
+    /// User left the chat before answering
+    Left,
+    /// User was kicked without a question
+    Kicked,
+}
+
+impl ChallengeState {
+    /// Whether an outcome is applied and challenge can not be changed anymore
+    pub fn is_final(self) -> bool {
+        !matches!(self, ChallengeState::Scheduled | ChallengeState::Asked)
+    }
+}
+
+/// A result of an attempt to ask a scheduled challenge
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TryAsk {
+    /// Challenge is asked now
+    Asked,
+    /// Challenge is not found or not scheduled anymore
+    NotScheduled,
+    /// There are too many asked challenges in the chat
+    Full,
+}
+
+/// Keeps states of active challenges
+///
+/// Every outcome must be applied only after a successful transition,
+/// so an answer and a timeout never apply their actions both.
+#[derive(Clone, Default)]
+pub struct ChallengeRegistry {
+    inner: Arc<Mutex<Challenges>>,
+}
+
+#[derive(Default)]
+struct Challenges {
+    states: HashMap<(Integer, Integer), ChallengeState>,
+    /// Number of asked challenges by chat ID
+    asked: HashMap<Integer, usize>,
+}
+
+impl Challenges {
+    /// Replaces a state and keeps number of asked challenges up to date
+    fn set(&mut self, chat_id: Integer, user_id: Integer, state: Option<ChallengeState>) {
+        let key = (chat_id, user_id);
+        let previous = match state {
+            Some(state) => self.states.insert(key, state),
+            None => self.states.remove(&key),
+        };
+        if previous == Some(ChallengeState::Asked) {
+            if let Some(count) = self.asked.get_mut(&chat_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.asked.remove(&chat_id);
+                }
+            }
+        }
+        if state == Some(ChallengeState::Asked) {
+            *self.asked.entry(chat_id).or_default() += 1;
+        }
+    }
+}
+
+impl ChallengeRegistry {
+    /// Starts a new challenge, previous one is replaced
+    pub fn start(&self, chat_id: Integer, user_id: Integer, state: ChallengeState) {
+        self.lock().set(chat_id, user_id, Some(state));
+    }
+
+    /// Returns a state of an active challenge
+    pub fn get(&self, chat_id: Integer, user_id: Integer) -> Option<ChallengeState> {
+        self.lock().states.get(&(chat_id, user_id)).copied()
+    }
+
+    /// Changes state from scheduled to asked if the chat has less than `max` asked challenges
+    ///
+    /// Limit is checked and state is changed under the same lock,
+    /// so concurrent questions never exceed the limit.
+    pub fn try_ask(&self, chat_id: Integer, user_id: Integer, max: Option<usize>) -> TryAsk {
+        let mut challenges = self.lock();
+        if challenges.states.get(&(chat_id, user_id)) != Some(&ChallengeState::Scheduled) {
+            return TryAsk::NotScheduled;
+        }
+        let asked = challenges.asked.get(&chat_id).copied().unwrap_or(0);
+        if max.map_or(false, |max| asked >= max) {
+            return TryAsk::Full;
+        }
+        challenges.set(chat_id, user_id, Some(ChallengeState::Asked));
+        log::info!(
+            "Challenge state changed from {:?} to {:?} (chat_id={}, user_id={})",
+            ChallengeState::Scheduled,
+            ChallengeState::Asked,
+            chat_id,
+            user_id
+        );
+        TryAsk::Asked
+    }
+
+    /// Changes state only if current state is equal to `from`
+    ///
+    /// Returns false if challenge is not found or has another state.
+    /// Challenges in a final state are removed from registry.
+    pub fn transition(&self, chat_id: Integer, user_id: Integer, from: ChallengeState, to: ChallengeState) -> bool {
+        let mut challenges = self.lock();
+        match challenges.states.get(&(chat_id, user_id)) {
+            Some(state) if *state == from => {
+                challenges.set(chat_id, user_id, if to.is_final() { None } else { Some(to) });
+                log::info!(
+                    "Challenge state changed from {:?} to {:?} (chat_id={}, user_id={})",
+                    from,
+                    to,
+                    chat_id,
+                    user_id
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes a challenge without an outcome if current state is equal to `from`
+    ///
+    /// Used for users who must not be verified, e.g. admins.
+    /// Returns false if challenge is not found or has another state.
+    pub fn remove(&self, chat_id: Integer, user_id: Integer, from: ChallengeState) -> bool {
+        let mut challenges = self.lock();
+        if challenges.states.get(&(chat_id, user_id)) != Some(&from) {
+            return false;
+        }
+        challenges.set(chat_id, user_id, None);
+        log::info!(
+            "Challenge removed in state {:?} (chat_id={}, user_id={})",
+            from,
+            chat_id,
+            user_id
+        );
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Challenges> {
+        self.inner.lock().expect("Failed to lock challenges")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_ask() {
+        let registry = ChallengeRegistry::default();
+        assert_eq!(registry.try_ask(1, 1, Some(1)), TryAsk::NotScheduled);
+        registry.start(1, 1, ChallengeState::Scheduled);
+        registry.start(1, 2, ChallengeState::Scheduled);
+        registry.start(2, 1, ChallengeState::Scheduled);
+        assert_eq!(registry.try_ask(1, 1, Some(1)), TryAsk::Asked);
+        assert_eq!(registry.get(1, 1), Some(ChallengeState::Asked));
+        assert_eq!(registry.try_ask(1, 1, Some(1)), TryAsk::NotScheduled);
+        assert_eq!(registry.try_ask(1, 2, Some(1)), TryAsk::Full);
+        assert_eq!(registry.get(1, 2), Some(ChallengeState::Scheduled));
+        // Limit is per chat
+        assert_eq!(registry.try_ask(2, 1, Some(1)), TryAsk::Asked);
+        assert_eq!(registry.try_ask(1, 2, None), TryAsk::Asked);
+    }
+
+    #[test]
+    fn asked_counting() {
+        let registry = ChallengeRegistry::default();
+        registry.start(1, 1, ChallengeState::Scheduled);
+        registry.start(1, 2, ChallengeState::Scheduled);
+        registry.start(1, 3, ChallengeState::Scheduled);
+        assert_eq!(registry.try_ask(1, 1, Some(2)), TryAsk::Asked);
+        assert_eq!(registry.try_ask(1, 2, Some(2)), TryAsk::Asked);
+        assert_eq!(registry.try_ask(1, 3, Some(2)), TryAsk::Full);
+        // A final state releases a slot and removes a challenge
+        assert!(registry.transition(1, 1, ChallengeState::Asked, ChallengeState::Passed));
+        assert_eq!(registry.get(1, 1), None);
+        assert!(!registry.transition(1, 1, ChallengeState::Asked, ChallengeState::Failed));
+        assert_eq!(registry.try_ask(1, 3, Some(2)), TryAsk::Asked);
+        // Restarting an asked challenge releases its slot too
+        registry.start(1, 2, ChallengeState::Scheduled);
+        registry.start(1, 4, ChallengeState::Scheduled);
+        assert_eq!(registry.try_ask(1, 4, Some(2)), TryAsk::Asked);
+        assert_eq!(registry.try_ask(1, 2, Some(2)), TryAsk::Full);
+        assert!(registry.transition(1, 3, ChallengeState::Asked, ChallengeState::Left));
+        assert!(registry.transition(1, 4, ChallengeState::Asked, ChallengeState::Expired));
+        assert!(registry.lock().asked.is_empty());
+    }
+
+    #[test]
+    fn remove() {
+        let registry = ChallengeRegistry::default();
+        registry.start(1, 1, ChallengeState::Scheduled);
+        assert!(!registry.remove(1, 1, ChallengeState::Asked));
+        assert_eq!(registry.get(1, 1), Some(ChallengeState::Scheduled));
+        assert!(registry.remove(1, 1, ChallengeState::Scheduled));
+        assert_eq!(registry.get(1, 1), None);
+        assert!(!registry.remove(1, 1, ChallengeState::Scheduled));
+        registry.start(1, 2, ChallengeState::Scheduled);
+        assert_eq!(registry.try_ask(1, 2, Some(1)), TryAsk::Asked);
+        assert!(registry.remove(1, 2, ChallengeState::Asked));
+        assert!(registry.lock().asked.is_empty());
+    }
+}
@@ -1,61 +1,177 @@
-use crate::config::raw::{RawAction, RawChatConfig};
-use carapax::types::{Integer, MentionError, ParseMode, User};
-use liquid::{value::liquid_value, Error as TemplateError, ParserBuilder as TemplateParserBuilder, Template};
-use std::{collections::HashMap, error::Error, fmt, sync::Arc, time::Duration};
+use crate::config::{
+    markup::{self, MarkupError},
+    name_rule::NameRule,
+    network::NetworkConfig,
+    raw::{
+        RawAction, RawButtonConfig, RawChatConfig, RawLevelConfig, RawLockdownAction, RawMediaConfig, RawMediaKind,
+        RawOverflow, RawParseMode,
+    },
+    risk::{RiskConfig, RiskConfigError},
+    template::{self, ChatInfo, LockdownVars, NotificationVars, QuestionVars, RenderTemplateError},
+};
+use carapax::types::{Integer, ParseMode, User};
+use liquid::{Error as TemplateError, Parser as TemplateParser, ParserBuilder as TemplateParserBuilder, Template};
+use regex::Error as RegexError;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 pub const DEFAULT_NOTIFICATION_RIGHT: &str = "Ok";
 pub const DEFAULT_NOTIFICATION_WRONG: &str = "Wrong!";
 pub const DEFAULT_NOTIFICATION_FORBIDDEN: &str = "You are not allowed to press this button!";
 
+/// Lockdown ends when no users joined within 5 minutes by default
+const DEFAULT_LOCKDOWN_QUIET_PERIOD: u64 = 300;
+
+const DEFAULT_LOCKDOWN_STARTED_TEXT: &str =
+    "Too many users joined the chat, new members are verified strictly for a while.";
+const DEFAULT_LOCKDOWN_ENDED_TEXT: &str = "Lockdown is over, {{joins}} user(s) joined during lockdown.";
+
+/// Maximum length of a media caption allowed by telegram
+const MAX_CAPTION_LENGTH: usize = 1024;
+
+/// Telegram does not allow to edit messages too often
+const MIN_COUNTDOWN_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct ChatConfig {
-    question: Arc<Template>,
-    buttons: Vec<ButtonConfig>,
+    parse_mode: Option<ParseMode>,
+    media: Option<MediaConfig>,
     ask_timeout: Option<Duration>,
-    response_timeout: Duration,
-    notification_right: String,
-    notification_wrong: String,
-    notification_forbidden: String,
+    batch_window: Option<Duration>,
+    max_pending: Option<usize>,
+    overflow: Overflow,
+    countdown_interval: Option<Duration>,
+    challenges: HashMap<ChallengeLevel, ChallengeConfig>,
+    notification_right: Arc<Template>,
+    notification_wrong: Arc<Template>,
+    notification_forbidden: Arc<Template>,
     action_wrong: Action,
     action_timeout: Action,
+    restrict_for: Option<Duration>,
+    welcome: Option<WelcomeConfig>,
+    rejoin: Option<RejoinConfig>,
+    bypass: BypassConfig,
+    bots: Option<BotsConfig>,
+    network: Option<Arc<NetworkConfig>>,
+    admin_chat_id: Option<Integer>,
+    name_rules: Vec<NameRule>,
+    raid: Option<RaidConfig>,
+    risk: Option<RiskConfig>,
 }
 
 impl ChatConfig {
-    pub fn render_question(&self, user: &User, parse_mode: ParseMode) -> Result<String, RenderQuestionError> {
-        let user = user.get_mention(parse_mode).map_err(RenderQuestionError::Mention)?;
-        let vars = liquid_value!({ "user": user })
-            .into_object()
-            .ok_or(RenderQuestionError::CreateVariables)?;
-        let rendered = self
-            .question
-            .render(&vars)
-            .map_err(RenderQuestionError::Render)?
-            .trim()
-            .to_string();
-        Ok(rendered)
+    /// Returns a parse mode for question and welcome messages
+    ///
+    /// None means plain text
+    pub fn parse_mode(&self) -> Option<ParseMode> {
+        self.parse_mode
     }
 
-    pub fn buttons(&self) -> &[ButtonConfig] {
-        &self.buttons
+    /// Returns a media to send with question
+    pub fn media(&self) -> Option<&MediaConfig> {
+        self.media.as_ref()
+    }
+
+    pub fn render_question(&self, level: ChallengeLevel, vars: &QuestionVars) -> Result<String, RenderTemplateError> {
+        let challenge = self.challenge(level);
+        let vars = vars.to_object(self.parse_mode, challenge.response_timeout, challenge.buttons.len())?;
+        template::render(&challenge.question, &vars)
+    }
+
+    /// Returns a rendered welcome message, if it is enabled for the chat
+    pub fn render_welcome(
+        &self,
+        level: ChallengeLevel,
+        vars: &QuestionVars,
+    ) -> Result<Option<String>, RenderTemplateError> {
+        match self.welcome {
+            Some(ref welcome) => {
+                let challenge = self.challenge(level);
+                let vars = vars.to_object(self.parse_mode, challenge.response_timeout, challenge.buttons.len())?;
+                template::render(&welcome.text, &vars).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a timeout after which welcome message will be deleted
+    pub fn welcome_delete_after(&self) -> Option<Duration> {
+        self.welcome.as_ref().and_then(|x| x.delete_after)
+    }
+
+    pub fn buttons(&self, level: ChallengeLevel) -> &[ButtonConfig] {
+        &self.challenge(level).buttons
     }
 
     pub fn ask_timeout(&self) -> Option<Duration> {
         self.ask_timeout
     }
 
-    pub fn response_timeout(&self) -> Duration {
-        self.response_timeout
+    /// Returns a period to collect new chat members for a single question
+    ///
+    /// Users joined with the same message are always asked together when it is set.
+    pub fn batch_window(&self) -> Option<Duration> {
+        self.batch_window
+    }
+
+    /// Returns how many questions can wait for an answer in the chat at the same time
+    pub fn max_pending(&self) -> Option<usize> {
+        self.max_pending
+    }
+
+    /// Returns what to do with users who can not be asked because of `max_pending`
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    pub fn response_timeout(&self, level: ChallengeLevel) -> Duration {
+        self.challenge(level).response_timeout
+    }
+
+    /// Returns an interval between question updates
+    ///
+    /// Question is not updated when interval is not set
+    pub fn countdown_interval(&self) -> Option<Duration> {
+        self.countdown_interval
+    }
+
+    fn challenge(&self, level: ChallengeLevel) -> &ChallengeConfig {
+        // Config contains all levels, see from_raw()
+        &self.challenges[&level]
+    }
+
+    /// Returns a rendered message announcing a lockdown, if lockdown is enabled for the chat
+    pub fn render_lockdown_started(&self, vars: &LockdownVars) -> Result<Option<String>, RenderTemplateError> {
+        match self.raid.as_ref().and_then(|x| x.lockdown.as_ref()) {
+            Some(lockdown) => template::render(&lockdown.started_text, &vars.to_object(self.parse_mode)?).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a rendered summary of a lockdown, if lockdown is enabled for the chat
+    pub fn render_lockdown_ended(&self, vars: &LockdownVars) -> Result<Option<String>, RenderTemplateError> {
+        match self.raid.as_ref().and_then(|x| x.lockdown.as_ref()) {
+            Some(lockdown) => template::render(&lockdown.ended_text, &vars.to_object(self.parse_mode)?).map(Some),
+            None => Ok(None),
+        }
     }
 
-    pub fn notification_right(&self) -> &str {
-        &self.notification_right
+    pub fn render_notification_right(&self, vars: &NotificationVars) -> Result<String, RenderTemplateError> {
+        template::render(&self.notification_right, &vars.to_object()?)
     }
 
-    pub fn notification_wrong(&self) -> &str {
-        &self.notification_wrong
+    pub fn render_notification_wrong(&self, vars: &NotificationVars) -> Result<String, RenderTemplateError> {
+        template::render(&self.notification_wrong, &vars.to_object()?)
     }
 
-    pub fn notification_forbidden(&self) -> &str {
-        &self.notification_forbidden
+    pub fn render_notification_forbidden(&self, vars: &NotificationVars) -> Result<String, RenderTemplateError> {
+        template::render(&self.notification_forbidden, &vars.to_object()?)
     }
 
     pub fn action_timeout(&self) -> Action {
@@ -65,8 +181,298 @@ impl ChatConfig {
     pub fn action_wrong(&self) -> Action {
         self.action_wrong
     }
+
+    /// Returns rules to skip verification
+    pub fn bypass(&self) -> &BypassConfig {
+        &self.bypass
+    }
+
+    /// Returns a policy for bots added to the chat
+    ///
+    /// None means that bots are verified like other users
+    pub fn bots(&self) -> Option<&BotsConfig> {
+        self.bots.as_ref()
+    }
+
+    /// Returns a chat to send alerts to
+    pub fn admin_chat_id(&self) -> Option<Integer> {
+        self.admin_chat_id
+    }
+
+    /// Returns rules to check names of new chat members
+    pub fn name_rules(&self) -> &[NameRule] {
+        &self.name_rules
+    }
+
+    /// Returns a join rate which is considered as a raid
+    pub fn raid(&self) -> Option<&RaidConfig> {
+        self.raid.as_ref()
+    }
+
+    /// Returns parameters to choose verification by a risk score
+    pub fn risk(&self) -> Option<&RiskConfig> {
+        self.risk.as_ref()
+    }
+
+    /// Returns a network the chat belongs to
+    pub fn network(&self) -> Option<&NetworkConfig> {
+        self.network.as_deref()
+    }
+
+    /// Returns a limit of leaving before answering
+    ///
+    /// None means that user is never banned for rejoining
+    pub fn rejoin(&self) -> Option<&RejoinConfig> {
+        self.rejoin.as_ref()
+    }
+
+    /// Returns a duration of restriction applied by restrict action
+    ///
+    /// None means forever
+    pub fn restrict_for(&self) -> Option<Duration> {
+        self.restrict_for
+    }
+}
+
+pub struct MediaConfig {
+    kind: MediaKind,
+    source: MediaSource,
+}
+
+impl MediaConfig {
+    fn from_raw(raw: RawMediaConfig) -> Result<Self, MediaConfigError> {
+        let kind = match raw.kind {
+            RawMediaKind::Animation => MediaKind::Animation,
+            RawMediaKind::Photo => MediaKind::Photo,
+            RawMediaKind::Sticker => MediaKind::Sticker,
+        };
+        let source = match (raw.path, raw.file_id) {
+            (Some(path), None) => {
+                if !path.is_file() {
+                    return Err(MediaConfigError::FileNotFound(path));
+                }
+                MediaSource::Path(path)
+            }
+            (None, Some(file_id)) => MediaSource::FileId(file_id),
+            _ => return Err(MediaConfigError::Source),
+        };
+        Ok(Self { kind, source })
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        self.kind
+    }
+
+    pub fn source(&self) -> &MediaSource {
+        &self.source
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MediaKind {
+    Animation,
+    Photo,
+    Sticker,
+}
+
+impl MediaKind {
+    /// Whether question can be sent as a caption for media
+    pub fn has_caption(self) -> bool {
+        match self {
+            MediaKind::Animation | MediaKind::Photo => true,
+            MediaKind::Sticker => false,
+        }
+    }
+}
+
+pub enum MediaSource {
+    /// A file_id that exists on the Telegram servers
+    FileId(String),
+    /// A local file to upload
+    Path(PathBuf),
+}
+
+impl MediaSource {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            MediaSource::FileId(_) => None,
+            MediaSource::Path(path) => Some(path),
+        }
+    }
+}
+
+struct WelcomeConfig {
+    text: Arc<Template>,
+    delete_after: Option<Duration>,
+}
+
+pub struct BypassConfig {
+    admins: bool,
+    added_by_admin: bool,
+    users: HashSet<Integer>,
+}
+
+impl BypassConfig {
+    /// Whether administrators are not verified
+    pub fn admins(&self) -> bool {
+        self.admins
+    }
+
+    /// Whether users added by an administrator are not verified
+    pub fn added_by_admin(&self) -> bool {
+        self.added_by_admin
+    }
+
+    /// Whether user is in allowlist
+    pub fn has_user(&self, user_id: Integer) -> bool {
+        self.users.contains(&user_id)
+    }
+}
+
+pub struct BotsConfig {
+    added_by_admin: bool,
+    usernames: HashSet<String>,
+}
+
+impl BotsConfig {
+    /// Whether bots added by an administrator are allowed
+    pub fn added_by_admin(&self) -> bool {
+        self.added_by_admin
+    }
+
+    /// Whether bot username is in allowlist
+    pub fn has_username(&self, username: &str) -> bool {
+        self.usernames.contains(&normalize_username(username))
+    }
+}
+
+fn normalize_username(username: &str) -> String {
+    username.trim_start_matches('@').to_lowercase()
+}
+
+pub struct RejoinConfig {
+    limit: usize,
+    period: Duration,
+}
+
+impl RejoinConfig {
+    /// Returns how many times user can leave a chat before answering
+    ///
+    /// User is banned on the next join after the limit is exceeded
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns a period to count leaves within
+    pub fn period(&self) -> Duration {
+        self.period
+    }
 }
 
+pub struct RaidConfig {
+    joins: usize,
+    period: Duration,
+    lockdown: Option<LockdownConfig>,
+}
+
+impl RaidConfig {
+    /// Returns how many users can join within a period
+    ///
+    /// Next joins are considered as a raid
+    pub fn joins(&self) -> usize {
+        self.joins
+    }
+
+    /// Returns a period to count joins within
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns parameters of a lockdown started on raid
+    pub fn lockdown(&self) -> Option<&LockdownConfig> {
+        self.lockdown.as_ref()
+    }
+}
+
+pub struct LockdownConfig {
+    verification: Verification,
+    quiet_period: Duration,
+    started_text: Arc<Template>,
+    ended_text: Arc<Template>,
+}
+
+impl LockdownConfig {
+    /// Returns what to do with users joined during lockdown
+    pub fn verification(&self) -> Verification {
+        self.verification.clone()
+    }
+
+    /// Returns a period without joins after which lockdown ends
+    pub fn quiet_period(&self) -> Duration {
+        self.quiet_period
+    }
+}
+
+/// Strictness of a question
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum ChallengeLevel {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for ChallengeLevel {
+    fn default() -> Self {
+        ChallengeLevel::Normal
+    }
+}
+
+/// What to do with a new chat member
+#[derive(Clone, Debug)]
+pub enum Verification {
+    /// Do not verify
+    Skip,
+    /// Ask a question
+    Challenge(ChallengeLevel),
+    /// Kick without a question, user can join again after period
+    Kick { reason: String, period: Duration },
+    /// Ban without a question
+    Ban {
+        reason: String,
+        /// Whether to ban in all chats of the network when federated bans are enabled
+        federate: bool,
+    },
+}
+
+/// Question parameters for a challenge level
+#[derive(Clone)]
+struct ChallengeConfig {
+    question: Arc<Template>,
+    buttons: Vec<ButtonConfig>,
+    response_timeout: Duration,
+}
+
+impl ChallengeConfig {
+    /// Returns a config where parameters set in level config replace parameters of this one
+    fn merge(&self, tpl_parser: &TemplateParser, level: RawLevelConfig) -> Result<Self, ChatConfigError> {
+        Ok(Self {
+            question: match level.question {
+                Some(question) => parse_template(tpl_parser, &question)?,
+                None => self.question.clone(),
+            },
+            buttons: match level.buttons {
+                Some(buttons) => create_buttons(buttons),
+                None => self.buttons.clone(),
+            },
+            response_timeout: level
+                .response_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(self.response_timeout),
+        })
+    }
+}
+
+#[derive(Clone)]
 pub struct ButtonConfig {
     label: String,
     is_right: bool,
@@ -103,100 +509,322 @@ impl From<RawAction> for Action {
     }
 }
 
-#[derive(Debug)]
-pub enum RenderQuestionError {
-    CreateVariables,
-    Mention(MentionError),
-    Render(TemplateError),
-}
-
-impl fmt::Display for RenderQuestionError {
-    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
-        use self::RenderQuestionError::*;
-        match self {
-            CreateVariables => write!(out, "could not create variables for question template"),
-            Mention(err) => write!(out, "can not get user mention string: {}", err),
-            Render(err) => write!(out, "failed to render question: {}", err),
-        }
-    }
+/// What to do with a new chat member when there are too many pending questions
+#[derive(Debug, Clone, Copy)]
+pub enum Overflow {
+    /// Kick without a message in the chat, user can join again after a while
+    Kick,
+    /// Ask later, when a pending question is answered or expired
+    Queue,
+    /// Ask by a single question with other users
+    Batch,
 }
 
-impl Error for RenderQuestionError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        use self::RenderQuestionError::*;
-        match self {
-            CreateVariables => None,
-            Mention(err) => Some(err),
-            Render(err) => Some(err),
-        }
-    }
+fn create_buttons(raw: Vec<RawButtonConfig>) -> Vec<ButtonConfig> {
+    raw.into_iter()
+        .map(|button| ButtonConfig {
+            label: button.label,
+            is_right: button.is_right,
+        })
+        .collect()
 }
 
-pub(super) fn from_raw(raw: Vec<RawChatConfig>) -> Result<HashMap<Integer, ChatConfig>, ChatConfigError> {
+pub(super) fn from_raw(
+    raw: Vec<RawChatConfig>,
+    networks: &HashMap<Integer, Arc<NetworkConfig>>,
+) -> Result<HashMap<Integer, Arc<ChatConfig>>, ChatConfigError> {
     let tpl_parser = TemplateParserBuilder::with_liquid()
         .build()
         .map_err(ChatConfigError::CreateTemplateParser)?;
     let mut result = HashMap::with_capacity(raw.len());
     for config in raw {
-        let question = Arc::new(
-            tpl_parser
-                .parse(&config.question)
-                .map_err(ChatConfigError::ParseTemplate)?,
-        );
-        let buttons = config
-            .buttons
-            .into_iter()
-            .map(|button| ButtonConfig {
-                label: button.label,
-                is_right: button.is_right,
-            })
-            .collect();
+        let chat_id = config.chat_id;
+        let parse_mode = match config.parse_mode {
+            Some(RawParseMode::Html) | None => Some(ParseMode::Html),
+            Some(RawParseMode::Markdown) => Some(ParseMode::Markdown),
+            Some(RawParseMode::MarkdownV2) => Some(ParseMode::MarkdownV2),
+            Some(RawParseMode::Plain) => None,
+        };
+        let question = parse_template(&tpl_parser, &config.question)?;
+        let media = match config.media {
+            Some(media) => Some(MediaConfig::from_raw(media).map_err(|err| ChatConfigError::Media(chat_id, err))?),
+            None => None,
+        };
+        let buttons = create_buttons(config.buttons);
         let ask_timeout = config.ask_timeout.map(Duration::from_secs);
         let response_timeout = Duration::from_secs(config.response_timeout);
+        let countdown_interval = config.countdown_interval.map(Duration::from_secs);
+        if let Some(interval) = countdown_interval {
+            if interval < MIN_COUNTDOWN_INTERVAL {
+                return Err(ChatConfigError::CountdownInterval(chat_id));
+            }
+        }
+        let normal = ChallengeConfig {
+            question,
+            buttons,
+            response_timeout,
+        };
+        let easy = match config.easy {
+            Some(easy) => normal.merge(&tpl_parser, easy)?,
+            None => normal.clone(),
+        };
+        let hard = match config.hard {
+            Some(hard) => normal.merge(&tpl_parser, hard)?,
+            None => normal.clone(),
+        };
+        let mut challenges = HashMap::new();
+        challenges.insert(ChallengeLevel::Easy, easy);
+        challenges.insert(ChallengeLevel::Normal, normal);
+        challenges.insert(ChallengeLevel::Hard, hard);
         let (notification_right, notification_wrong, notification_forbidden) = config
             .notification
             .map(|x| (x.right, x.wrong, x.forbidden))
             .unwrap_or_else(|| (None, None, None));
-        let notification_right = notification_right.unwrap_or_else(|| String::from(DEFAULT_NOTIFICATION_RIGHT));
-        let notification_wrong = notification_wrong.unwrap_or_else(|| String::from(DEFAULT_NOTIFICATION_WRONG));
-        let notification_forbidden =
-            notification_forbidden.unwrap_or_else(|| String::from(DEFAULT_NOTIFICATION_FORBIDDEN));
-        let (action_timeout, action_wrong) = config
+        let notification_right = parse_notification(
+            &tpl_parser,
+            notification_right.as_deref().unwrap_or(DEFAULT_NOTIFICATION_RIGHT),
+        )?;
+        let notification_wrong = parse_notification(
+            &tpl_parser,
+            notification_wrong.as_deref().unwrap_or(DEFAULT_NOTIFICATION_WRONG),
+        )?;
+        let notification_forbidden = parse_notification(
+            &tpl_parser,
+            notification_forbidden
+                .as_deref()
+                .unwrap_or(DEFAULT_NOTIFICATION_FORBIDDEN),
+        )?;
+        let (action_timeout, action_wrong, restrict_for) = config
             .action
-            .map(|x| (x.timeout, x.wrong))
-            .unwrap_or_else(|| (None, None));
+            .map(|x| (x.timeout, x.wrong, x.restrict_for))
+            .unwrap_or_else(|| (None, None, None));
+        let restrict_for = restrict_for.map(Duration::from_secs);
         let action_timeout = action_timeout.map(Action::from).unwrap_or_else(Default::default);
         let action_wrong = action_wrong.map(Action::from).unwrap_or_else(Default::default);
-        result.insert(
-            config.chat_id,
-            ChatConfig {
-                question,
-                buttons,
-                ask_timeout,
-                response_timeout,
-                notification_right,
-                notification_wrong,
-                notification_forbidden,
-                action_timeout,
-                action_wrong,
+        let welcome = match config.welcome {
+            Some(welcome) => Some(WelcomeConfig {
+                text: parse_template(&tpl_parser, &welcome.text)?,
+                delete_after: welcome.delete_after.map(Duration::from_secs),
+            }),
+            None => None,
+        };
+        let rejoin = config.rejoin.map(|rejoin| RejoinConfig {
+            limit: rejoin.limit as usize,
+            period: Duration::from_secs(rejoin.period),
+        });
+        let (bypass_admins, bypass_added_by_admin, bypass_users) = config
+            .bypass
+            .map(|x| (x.admins, x.added_by_admin, x.users))
+            .unwrap_or_else(|| (None, None, None));
+        let bypass = BypassConfig {
+            admins: bypass_admins.unwrap_or(true),
+            added_by_admin: bypass_added_by_admin.unwrap_or(false),
+            users: bypass_users.unwrap_or_default().into_iter().collect(),
+        };
+        let bots = config.bots.map(|bots| BotsConfig {
+            added_by_admin: bots.added_by_admin.unwrap_or(true),
+            usernames: bots
+                .usernames
+                .unwrap_or_default()
+                .iter()
+                .map(|x| normalize_username(x))
+                .collect(),
+        });
+        let name_rules = config
+            .name_rules
+            .unwrap_or_default()
+            .into_iter()
+            .map(NameRule::from_raw)
+            .collect::<Result<Vec<NameRule>, RegexError>>()
+            .map_err(|err| ChatConfigError::NameRule(chat_id, err))?;
+        let raid = match config.raid {
+            Some(raid) => Some(RaidConfig {
+                joins: raid.joins as usize,
+                period: Duration::from_secs(raid.period),
+                lockdown: match raid.lockdown {
+                    Some(lockdown) => {
+                        let quiet_period =
+                            Duration::from_secs(lockdown.quiet_period.unwrap_or(DEFAULT_LOCKDOWN_QUIET_PERIOD));
+                        Some(LockdownConfig {
+                            verification: match lockdown.action {
+                                // Users can join again when lockdown is likely over
+                                Some(RawLockdownAction::Kick) => Verification::Kick {
+                                    reason: String::from("joined during lockdown"),
+                                    period: quiet_period,
+                                },
+                                Some(RawLockdownAction::Hard) | None => Verification::Challenge(ChallengeLevel::Hard),
+                            },
+                            quiet_period,
+                            started_text: parse_template(
+                                &tpl_parser,
+                                lockdown
+                                    .started_text
+                                    .as_deref()
+                                    .unwrap_or(DEFAULT_LOCKDOWN_STARTED_TEXT),
+                            )?,
+                            ended_text: parse_template(
+                                &tpl_parser,
+                                lockdown.ended_text.as_deref().unwrap_or(DEFAULT_LOCKDOWN_ENDED_TEXT),
+                            )?,
+                        })
+                    }
+                    None => None,
+                },
+            }),
+            None => None,
+        };
+        let risk = match config.risk {
+            Some(risk) => Some(RiskConfig::from_raw(risk).map_err(|err| ChatConfigError::Risk(chat_id, err))?),
+            None => None,
+        };
+        let chat_config = ChatConfig {
+            parse_mode,
+            media,
+            ask_timeout,
+            batch_window: config.batch_window.map(Duration::from_secs),
+            max_pending: config.max_pending.map(|x| x as usize),
+            overflow: match config.overflow {
+                Some(RawOverflow::Kick) => Overflow::Kick,
+                Some(RawOverflow::Queue) | None => Overflow::Queue,
+                Some(RawOverflow::Batch) => Overflow::Batch,
             },
-        );
+            countdown_interval,
+            challenges,
+            notification_right,
+            notification_wrong,
+            notification_forbidden,
+            action_timeout,
+            action_wrong,
+            restrict_for,
+            welcome,
+            rejoin,
+            bypass,
+            bots,
+            network: networks.get(&chat_id).cloned(),
+            admin_chat_id: config.admin_chat_id,
+            name_rules,
+            raid,
+            risk,
+        };
+        validate_messages(&chat_config).map_err(|err| ChatConfigError::InvalidMessage(chat_id, err))?;
+        result.insert(chat_id, Arc::new(chat_config));
     }
     Ok(result)
 }
 
+/// Renders questions, welcome and lockdown messages for a dummy user in order to find errors at startup
+fn validate_messages(config: &ChatConfig) -> Result<(), InvalidMessageError> {
+    let user = create_dummy_user();
+    for level in &[ChallengeLevel::Easy, ChallengeLevel::Normal, ChallengeLevel::Hard] {
+        let vars = QuestionVars {
+            user: &user,
+            batch: &[],
+            chat: create_dummy_chat(),
+            joined_at: 0,
+            remaining: config.response_timeout(*level),
+        };
+        let question = config
+            .render_question(*level, &vars)
+            .map_err(InvalidMessageError::Render)?;
+        markup::validate(&question, config.parse_mode).map_err(InvalidMessageError::Markup)?;
+        if let Some(ref media) = config.media {
+            if media.kind.has_caption() && question.chars().count() > MAX_CAPTION_LENGTH {
+                return Err(InvalidMessageError::CaptionTooLong);
+            }
+        }
+        if let Some(welcome) = config
+            .render_welcome(*level, &vars)
+            .map_err(InvalidMessageError::Render)?
+        {
+            markup::validate(&welcome, config.parse_mode).map_err(InvalidMessageError::Markup)?;
+        }
+    }
+    let vars = LockdownVars {
+        chat: create_dummy_chat(),
+        joins: 1,
+    };
+    let started = config
+        .render_lockdown_started(&vars)
+        .map_err(InvalidMessageError::Render)?;
+    let ended = config
+        .render_lockdown_ended(&vars)
+        .map_err(InvalidMessageError::Render)?;
+    for text in started.iter().chain(ended.iter()) {
+        markup::validate(text, config.parse_mode).map_err(InvalidMessageError::Markup)?;
+    }
+    Ok(())
+}
+
+fn create_dummy_user() -> User {
+    // Name contains special characters in order to make sure that mention is escaped properly
+    User {
+        id: 1,
+        is_bot: false,
+        first_name: String::from("<John>"),
+        last_name: Some(String::from("*Doe_")),
+        username: Some(String::from("johndoe")),
+        language_code: None,
+    }
+}
+
+fn create_dummy_chat() -> ChatInfo<'static> {
+    ChatInfo {
+        id: 1,
+        title: Some("Chat"),
+        username: Some("chat"),
+    }
+}
+
+fn parse_template(parser: &TemplateParser, source: &str) -> Result<Arc<Template>, ChatConfigError> {
+    parser
+        .parse(source)
+        .map(Arc::new)
+        .map_err(ChatConfigError::ParseTemplate)
+}
+
+fn parse_notification(parser: &TemplateParser, source: &str) -> Result<Arc<Template>, ChatConfigError> {
+    let template = parse_template(parser, source)?;
+    // Render template with dummy variables in order to find unknown variables at startup
+    let user = create_dummy_user();
+    let vars = NotificationVars {
+        user: &user,
+        chat: create_dummy_chat(),
+        remaining: Duration::from_secs(1),
+    };
+    template::render(&template, &vars.to_object().map_err(ChatConfigError::RenderTemplate)?)
+        .map_err(ChatConfigError::RenderTemplate)?;
+    Ok(template)
+}
+
 #[derive(Debug)]
 pub enum ChatConfigError {
+    CountdownInterval(Integer),
     CreateTemplateParser(TemplateError),
     ParseTemplate(TemplateError),
+    InvalidMessage(Integer, InvalidMessageError),
+    Media(Integer, MediaConfigError),
+    NameRule(Integer, RegexError),
+    RenderTemplate(RenderTemplateError),
+    Risk(Integer, RiskConfigError),
 }
 
 impl fmt::Display for ChatConfigError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::ChatConfigError::*;
         match self {
+            CountdownInterval(chat_id) => write!(
+                out,
+                "countdown interval must be at least {} seconds (chat_id={})",
+                MIN_COUNTDOWN_INTERVAL.as_secs(),
+                chat_id
+            ),
             CreateTemplateParser(err) => write!(out, "{}", err),
             ParseTemplate(err) => write!(out, "{}", err),
+            InvalidMessage(chat_id, err) => write!(out, "{} (chat_id={})", err, chat_id),
+            Media(chat_id, err) => write!(out, "{} (chat_id={})", err, chat_id),
+            NameRule(chat_id, err) => write!(out, "invalid name rule: {} (chat_id={})", err, chat_id),
+            RenderTemplate(err) => write!(out, "{}", err),
+            Risk(chat_id, err) => write!(out, "{} (chat_id={})", err, chat_id),
         }
     }
 }
@@ -204,9 +832,66 @@ impl fmt::Display for ChatConfigError {
 impl Error for ChatConfigError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::ChatConfigError::*;
-        Some(match self {
-            CreateTemplateParser(err) => err,
-            ParseTemplate(err) => err,
-        })
+        match self {
+            CountdownInterval(_) => None,
+            CreateTemplateParser(err) => Some(err),
+            ParseTemplate(err) => Some(err),
+            InvalidMessage(_, err) => Some(err),
+            Media(_, err) => Some(err),
+            NameRule(_, err) => Some(err),
+            RenderTemplate(err) => Some(err),
+            Risk(_, err) => Some(err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidMessageError {
+    CaptionTooLong,
+    Markup(MarkupError),
+    Render(RenderTemplateError),
+}
+
+impl fmt::Display for InvalidMessageError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::InvalidMessageError::*;
+        match self {
+            CaptionTooLong => write!(
+                out,
+                "question is too long for a caption (max {} chars)",
+                MAX_CAPTION_LENGTH
+            ),
+            Markup(err) => write!(out, "invalid markup: {}", err),
+            Render(err) => write!(out, "{}", err),
+        }
     }
 }
+
+impl Error for InvalidMessageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::InvalidMessageError::*;
+        match self {
+            CaptionTooLong => None,
+            Markup(err) => Some(err),
+            Render(err) => Some(err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MediaConfigError {
+    FileNotFound(PathBuf),
+    Source,
+}
+
+impl fmt::Display for MediaConfigError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::MediaConfigError::*;
+        match self {
+            FileNotFound(path) => write!(out, "media file '{}' not found", path.display()),
+            Source => write!(out, "either path or file_id must be specified for media"),
+        }
+    }
+}
+
+impl Error for MediaConfigError {}
@@ -0,0 +1,174 @@
+use carapax::types::ParseMode;
+use std::{error::Error, fmt};
+
+/// Tags supported by telegram in HTML parse mode
+const HTML_TAGS: [&str; 12] = [
+    "a", "b", "code", "del", "em", "i", "ins", "pre", "s", "strike", "strong", "u",
+];
+
+/// Characters which must be escaped in MarkdownV2 when they are not a part of an entity
+const MARKDOWN_V2_RESERVED: [char; 12] = ['.', '-', '!', '(', ')', '>', '#', '+', '=', '|', '{', '}'];
+
+/// Performs a basic check of a rendered text
+///
+/// Telegram rejects a message with unclosed entities,
+/// so we try to find them before sending a message.
+pub(super) fn validate(text: &str, parse_mode: Option<ParseMode>) -> Result<(), MarkupError> {
+    match parse_mode {
+        Some(ParseMode::Html) => validate_html(text),
+        Some(ParseMode::Markdown) => validate_markdown(text, &['*', '_', '`'], &[]),
+        Some(ParseMode::MarkdownV2) => validate_markdown(text, &['*', '_', '`', '~'], &MARKDOWN_V2_RESERVED),
+        None => Ok(()),
+    }
+}
+
+fn validate_html(text: &str) -> Result<(), MarkupError> {
+    let mut stack = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => return Err(MarkupError::UnclosedTag(String::from(&rest[start..]))),
+        };
+        let tag = &rest[start + 1..end];
+        let (is_closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let name = tag.split_whitespace().next().unwrap_or("").to_lowercase();
+        if !HTML_TAGS.contains(&name.as_str()) {
+            return Err(MarkupError::UnsupportedTag(name));
+        }
+        if is_closing {
+            match stack.pop() {
+                Some(open) if open == name => {}
+                _ => return Err(MarkupError::UnexpectedClosingTag(name)),
+            }
+        } else {
+            stack.push(name);
+        }
+        rest = &rest[end + 1..];
+    }
+    match stack.pop() {
+        Some(name) => Err(MarkupError::UnclosedTag(name)),
+        None => Ok(()),
+    }
+}
+
+fn validate_markdown(text: &str, delimiters: &[char], reserved: &[char]) -> Result<(), MarkupError> {
+    let mut open: Vec<char> = Vec::new();
+    let mut escaped = false;
+    let mut is_url = false;
+    let mut prev = None;
+    for c in text.chars() {
+        let is_code = open.last() == Some(&'`');
+        if escaped {
+            escaped = false;
+        } else if is_url {
+            // Link URL may contain any characters except of closing parenthesis
+            is_url = c != ')';
+        } else if c == '\\' && !is_code {
+            escaped = true;
+        } else if c == '(' && prev == Some(']') && !is_code {
+            is_url = true;
+        } else if delimiters.contains(&c) && (!is_code || c == '`') {
+            if open.last() == Some(&c) {
+                open.pop();
+            } else {
+                open.push(c);
+            }
+        } else if reserved.contains(&c) && !is_code {
+            return Err(MarkupError::UnescapedCharacter(c));
+        }
+        prev = Some(c);
+    }
+    match open.pop() {
+        Some(delimiter) => Err(MarkupError::UnclosedEntity(delimiter)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug)]
+pub enum MarkupError {
+    UnclosedEntity(char),
+    UnclosedTag(String),
+    UnescapedCharacter(char),
+    UnexpectedClosingTag(String),
+    UnsupportedTag(String),
+}
+
+impl fmt::Display for MarkupError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::MarkupError::*;
+        match self {
+            UnclosedEntity(delimiter) => write!(out, "entity started with '{}' is not closed", delimiter),
+            UnclosedTag(tag) => write!(out, "tag '{}' is not closed", tag),
+            UnescapedCharacter(c) => write!(out, "character '{}' must be escaped with '\\'", c),
+            UnexpectedClosingTag(tag) => write!(out, "unexpected closing tag '{}'", tag),
+            UnsupportedTag(tag) => write!(out, "tag '{}' is not supported", tag),
+        }
+    }
+}
+
+impl Error for MarkupError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html() {
+        assert!(validate("<b>bold</b> <a href=\"tg://user?id=1\">user</a>", Some(ParseMode::Html)).is_ok());
+        assert!(matches!(
+            validate("<b>bold", Some(ParseMode::Html)),
+            Err(MarkupError::UnclosedTag(_))
+        ));
+        assert!(matches!(
+            validate("<b>bold</i>", Some(ParseMode::Html)),
+            Err(MarkupError::UnexpectedClosingTag(_))
+        ));
+        assert!(matches!(
+            validate("<div>text</div>", Some(ParseMode::Html)),
+            Err(MarkupError::UnsupportedTag(_))
+        ));
+    }
+
+    #[test]
+    fn markdown() {
+        assert!(validate(
+            "*bold* _italic_ `code_` [user](tg://user?id=1).",
+            Some(ParseMode::Markdown)
+        )
+        .is_ok());
+        assert!(matches!(
+            validate("*bold", Some(ParseMode::Markdown)),
+            Err(MarkupError::UnclosedEntity('*'))
+        ));
+    }
+
+    #[test]
+    fn markdown_v2() {
+        assert!(validate(
+            "*bold* ~strike~ [user](tg://user?id=1) \\(1\\-2\\)\\! `a.b`",
+            Some(ParseMode::MarkdownV2)
+        )
+        .is_ok());
+        assert!(matches!(
+            validate("~strike", Some(ParseMode::MarkdownV2)),
+            Err(MarkupError::UnclosedEntity('~'))
+        ));
+        assert!(matches!(
+            validate("Welcome!", Some(ParseMode::MarkdownV2)),
+            Err(MarkupError::UnescapedCharacter('!'))
+        ));
+        assert!(matches!(
+            validate("1-2", Some(ParseMode::MarkdownV2)),
+            Err(MarkupError::UnescapedCharacter('-'))
+        ));
+    }
+
+    #[test]
+    fn plain() {
+        assert!(validate("<b *unclosed", None).is_ok());
+    }
+}
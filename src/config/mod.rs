@@ -1,27 +1,57 @@
 use carapax::{Config as ApiConfig, ParseProxyError};
-use std::{collections::HashMap, error::Error, fmt, net::AddrParseError, path::Path};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    net::AddrParseError,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 mod chat;
+mod markup;
+mod name_rule;
+mod network;
 mod raw;
+mod risk;
+mod template;
 mod webhook_url;
 
 use self::{
     chat::ChatConfigError,
+    network::NetworkConfigError,
     raw::{RawConfig, RawConfigError},
 };
 
 pub use self::{
     chat::{
-        Action, ButtonConfig, ChatConfig, RenderQuestionError, DEFAULT_NOTIFICATION_FORBIDDEN,
-        DEFAULT_NOTIFICATION_RIGHT, DEFAULT_NOTIFICATION_WRONG,
+        Action, ButtonConfig, ChallengeLevel, ChatConfig, LockdownConfig, MediaConfig, MediaKind, MediaSource,
+        Overflow, RaidConfig, RejoinConfig, Verification, DEFAULT_NOTIFICATION_FORBIDDEN, DEFAULT_NOTIFICATION_RIGHT,
+        DEFAULT_NOTIFICATION_WRONG,
     },
+    name_rule::NameRuleAction,
+    risk::RiskConfig,
+    template::{ChatInfo, LockdownVars, NotificationVars, QuestionVars, RenderTemplateError},
     webhook_url::WebhookUrl,
 };
 
 pub struct Config {
     pub api: ApiConfig,
     pub webhook_url: Option<WebhookUrl>,
-    pub chats: HashMap<i64, ChatConfig>,
+    /// A directory to store data which must survive restarts
+    pub data_dir: Option<PathBuf>,
+    pub blocklist: Option<BlocklistConfig>,
+    pub chats: HashMap<i64, Arc<ChatConfig>>,
+}
+
+const DEFAULT_BLOCKLIST_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct BlocklistConfig {
+    /// A file with IDs of users to ban on join
+    pub path: PathBuf,
+    /// How often to check whether file is changed
+    pub reload_interval: Duration,
 }
 
 impl Config {
@@ -38,11 +68,23 @@ impl Config {
             None => None,
         };
 
-        let chats = chat::from_raw(raw.chats)?;
+        let reload_interval = raw
+            .blocklist_reload_interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_BLOCKLIST_RELOAD_INTERVAL);
+        let blocklist = raw.blocklist_file.map(|path| BlocklistConfig { path, reload_interval });
+
+        let networks = network::from_raw(raw.networks.unwrap_or_default())?;
+        let chats = chat::from_raw(raw.chats, &networks)?;
+        if let Some(chat_id) = networks.keys().find(|chat_id| !chats.contains_key(chat_id)) {
+            return Err(ConfigError::UnknownNetworkChat(*chat_id));
+        }
 
         Ok(Config {
             api,
             webhook_url,
+            data_dir: raw.data_dir,
+            blocklist,
             chats,
         })
     }
@@ -51,8 +93,10 @@ impl Config {
 #[derive(Debug)]
 pub enum ConfigError {
     Chat(ChatConfigError),
+    Network(NetworkConfigError),
     ParseProxy(ParseProxyError),
     Raw(RawConfigError),
+    UnknownNetworkChat(i64),
     WebhookAddress(AddrParseError),
 }
 
@@ -62,6 +106,12 @@ impl From<ChatConfigError> for ConfigError {
     }
 }
 
+impl From<NetworkConfigError> for ConfigError {
+    fn from(err: NetworkConfigError) -> Self {
+        ConfigError::Network(err)
+    }
+}
+
 impl From<ParseProxyError> for ConfigError {
     fn from(err: ParseProxyError) -> Self {
         ConfigError::ParseProxy(err)
@@ -77,12 +127,14 @@ impl From<RawConfigError> for ConfigError {
 impl Error for ConfigError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::ConfigError::*;
-        Some(match self {
-            Chat(err) => err,
-            ParseProxy(err) => err,
-            Raw(err) => err,
-            WebhookAddress(err) => err,
-        })
+        match self {
+            Chat(err) => Some(err),
+            Network(err) => Some(err),
+            ParseProxy(err) => Some(err),
+            Raw(err) => Some(err),
+            UnknownNetworkChat(_) => None,
+            WebhookAddress(err) => Some(err),
+        }
     }
 }
 
@@ -91,8 +143,10 @@ impl fmt::Display for ConfigError {
         use self::ConfigError::*;
         match self {
             Chat(err) => write!(out, "{}", err),
+            Network(err) => write!(out, "{}", err),
             ParseProxy(err) => write!(out, "bad proxy address: {}", err),
             Raw(err) => write!(out, "{}", err),
+            UnknownNetworkChat(chat_id) => write!(out, "network contains chat {} which is not configured", chat_id),
             WebhookAddress(err) => write!(out, "bad webhook address: {}", err),
         }
     }
@@ -0,0 +1,111 @@
+use crate::config::raw::{RawNameField, RawNameRule, RawNameRuleAction};
+use carapax::types::User;
+use regex::{Error as RegexError, Regex};
+
+/// A rule to find suspicious users by their names
+pub struct NameRule {
+    regex: Regex,
+    fields: Vec<RawNameField>,
+    action: NameRuleAction,
+    federate: bool,
+}
+
+impl NameRule {
+    pub(super) fn from_raw(raw: RawNameRule) -> Result<Self, RegexError> {
+        Ok(Self {
+            regex: Regex::new(&raw.pattern)?,
+            fields: raw
+                .fields
+                .unwrap_or_else(|| vec![RawNameField::FirstName, RawNameField::LastName, RawNameField::Username]),
+            action: match raw.action {
+                RawNameRuleAction::Ban => NameRuleAction::Ban,
+                RawNameRuleAction::Hard => NameRuleAction::Hard,
+                RawNameRuleAction::Flag => NameRuleAction::Flag,
+            },
+            federate: raw.federate.unwrap_or(false),
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn action(&self) -> NameRuleAction {
+        self.action
+    }
+
+    /// Whether a ban by the rule is propagated to all chats of the network
+    ///
+    /// Names are easy to change and rules may match innocent users, so bans are local by default.
+    pub fn federate(&self) -> bool {
+        self.federate
+    }
+
+    /// Whether any of configured fields matches the rule
+    pub fn is_match(&self, user: &User) -> bool {
+        self.fields.iter().any(|field| {
+            let value = match field {
+                RawNameField::FirstName => Some(user.first_name.as_str()),
+                RawNameField::LastName => user.last_name.as_deref(),
+                RawNameField::Username => user.username.as_deref(),
+            };
+            value.map_or(false, |x| self.regex.is_match(x))
+        })
+    }
+}
+
+/// An action for a user matching a rule, ordered by strictness
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum NameRuleAction {
+    /// Notify admins
+    Flag,
+    /// Ask a hard question
+    Hard,
+    /// Ban without a question
+    Ban,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_rule(pattern: &str, fields: Option<Vec<RawNameField>>) -> NameRule {
+        NameRule::from_raw(RawNameRule {
+            pattern: String::from(pattern),
+            fields,
+            action: RawNameRuleAction::Flag,
+            federate: None,
+        })
+        .unwrap()
+    }
+
+    fn create_user(first_name: &str, last_name: Option<&str>, username: Option<&str>) -> User {
+        User {
+            id: 1,
+            is_bot: false,
+            first_name: String::from(first_name),
+            last_name: last_name.map(String::from),
+            username: username.map(String::from),
+            language_code: None,
+        }
+    }
+
+    #[test]
+    fn is_match() {
+        let rule = create_rule("(?i)crypto", None);
+        assert!(rule.is_match(&create_user("CryptoBot", None, None)));
+        assert!(rule.is_match(&create_user("John", Some("crypto"), None)));
+        assert!(rule.is_match(&create_user("John", None, Some("crypto_signals"))));
+        assert!(!rule.is_match(&create_user("John", None, None)));
+        let rule = create_rule("crypto", Some(vec![RawNameField::Username]));
+        assert!(!rule.is_match(&create_user("crypto", Some("crypto"), None)));
+        assert!(rule.is_match(&create_user("John", None, Some("crypto"))));
+        assert!(NameRule::from_raw(RawNameRule {
+            pattern: String::from("("),
+            fields: None,
+            action: RawNameRuleAction::Ban,
+            federate: None,
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::config::{
+    chat::{ChallengeLevel, Verification},
+    raw::{RawNetworkConfig, RawVerification},
+};
+use carapax::types::Integer;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc, time::Duration};
+
+/// A group of chats sharing trusted users
+pub struct NetworkConfig {
+    name: String,
+    chats: Vec<Integer>,
+    trusted: Verification,
+    trusted_for: Option<Duration>,
+    federated_bans: bool,
+    ban_for: Option<Duration>,
+}
+
+impl NetworkConfig {
+    /// Returns a name of the network, it is used as a key in store
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns IDs of chats in the network
+    pub fn chats(&self) -> &[Integer] {
+        &self.chats
+    }
+
+    /// Returns what to do with a user who passed a question in any chat of the network
+    pub fn trusted(&self) -> Verification {
+        self.trusted.clone()
+    }
+
+    /// Returns how long a user is trusted after a right answer, forever when not set
+    pub fn trusted_for(&self) -> Option<Duration> {
+        self.trusted_for
+    }
+
+    /// Whether a user banned in one chat is banned in all chats of the network
+    pub fn federated_bans(&self) -> bool {
+        self.federated_bans
+    }
+
+    /// Returns how long a federated ban lasts, forever when not set
+    pub fn ban_for(&self) -> Option<Duration> {
+        self.ban_for
+    }
+}
+
+/// Returns networks by chat ID
+pub(super) fn from_raw(raw: Vec<RawNetworkConfig>) -> Result<HashMap<Integer, Arc<NetworkConfig>>, NetworkConfigError> {
+    let mut result = HashMap::new();
+    for network in raw {
+        let is_valid_name = !network.name.is_empty()
+            && network
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid_name {
+            return Err(NetworkConfigError::InvalidName(network.name));
+        }
+        let trusted = match network.trusted.unwrap_or(RawVerification::Skip) {
+            RawVerification::Skip => Verification::Skip,
+            RawVerification::Easy => Verification::Challenge(ChallengeLevel::Easy),
+            RawVerification::Normal => Verification::Challenge(ChallengeLevel::Normal),
+            RawVerification::Hard => Verification::Challenge(ChallengeLevel::Hard),
+            // A user is not trusted anymore when banned, so trusted users could never join again
+            RawVerification::Ban => return Err(NetworkConfigError::TrustedBan(network.name)),
+        };
+        let network = Arc::new(NetworkConfig {
+            name: network.name,
+            chats: network.chats,
+            trusted,
+            trusted_for: network.trusted_for.map(Duration::from_secs),
+            federated_bans: network.federated_bans.unwrap_or(false),
+            ban_for: network.ban_for.map(Duration::from_secs),
+        });
+        for chat_id in network.chats() {
+            if result.insert(*chat_id, network.clone()).is_some() {
+                return Err(NetworkConfigError::DuplicateChat(*chat_id));
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub enum NetworkConfigError {
+    DuplicateChat(Integer),
+    InvalidName(String),
+    TrustedBan(String),
+}
+
+impl fmt::Display for NetworkConfigError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::NetworkConfigError::*;
+        match self {
+            DuplicateChat(chat_id) => write!(out, "chat {} belongs to more than one network", chat_id),
+            InvalidName(name) => write!(
+                out,
+                "invalid network name '{}', only latin letters, digits, '-' and '_' are allowed",
+                name
+            ),
+            TrustedBan(name) => write!(out, "trusted users can not be banned (network '{}')", name),
+        }
+    }
+}
+
+impl Error for NetworkConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(name: &str, chats: Vec<Integer>, trusted: Option<RawVerification>) -> RawNetworkConfig {
+        RawNetworkConfig {
+            name: String::from(name),
+            chats,
+            trusted,
+            trusted_for: None,
+            federated_bans: None,
+            ban_for: None,
+        }
+    }
+
+    #[test]
+    fn networks() {
+        let networks = from_raw(vec![raw("main", vec![1, 2], None), raw("other", vec![3], None)]).unwrap();
+        assert_eq!(networks.len(), 3);
+        assert_eq!(networks[&2].name(), "main");
+        assert!(matches!(networks[&1].trusted(), Verification::Skip));
+        assert!(matches!(
+            from_raw(vec![raw("main", vec![1], Some(RawVerification::Ban))]),
+            Err(NetworkConfigError::TrustedBan(_))
+        ));
+        assert!(matches!(
+            from_raw(vec![raw("main", vec![1], None), raw("other", vec![1], None)]),
+            Err(NetworkConfigError::DuplicateChat(1))
+        ));
+        assert!(matches!(
+            from_raw(vec![raw("main network", vec![1], None)]),
+            Err(NetworkConfigError::InvalidName(_))
+        ));
+    }
+}
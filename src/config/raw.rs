@@ -14,18 +14,115 @@ pub(super) struct RawConfig {
     pub(super) proxy: Option<String>,
     pub(super) webhook_address: Option<String>,
     pub(super) webhook_path: Option<String>,
+    pub(super) data_dir: Option<PathBuf>,
+    pub(super) blocklist_file: Option<PathBuf>,
+    pub(super) blocklist_reload_interval: Option<u64>,
     pub(super) chats: Vec<RawChatConfig>,
+    pub(super) networks: Option<Vec<RawNetworkConfig>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawNetworkConfig {
+    pub(super) name: String,
+    pub(super) chats: Vec<i64>,
+    pub(super) trusted: Option<RawVerification>,
+    pub(super) trusted_for: Option<u64>,
+    pub(super) federated_bans: Option<bool>,
+    pub(super) ban_for: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum RawVerification {
+    Skip,
+    Easy,
+    Normal,
+    Hard,
+    Ban,
 }
 
 #[derive(Deserialize)]
 pub(super) struct RawChatConfig {
     pub(super) chat_id: i64,
+    pub(super) parse_mode: Option<RawParseMode>,
     pub(super) question: String,
+    pub(super) media: Option<RawMediaConfig>,
     pub(super) buttons: Vec<RawButtonConfig>,
     pub(super) ask_timeout: Option<u64>,
+    pub(super) batch_window: Option<u64>,
+    pub(super) max_pending: Option<u64>,
+    pub(super) overflow: Option<RawOverflow>,
     pub(super) response_timeout: u64,
+    pub(super) countdown_interval: Option<u64>,
     pub(super) notification: Option<RawNotificationConfig>,
     pub(super) action: Option<RawActionConfig>,
+    pub(super) welcome: Option<RawWelcomeConfig>,
+    pub(super) rejoin: Option<RawRejoinConfig>,
+    pub(super) bypass: Option<RawBypassConfig>,
+    pub(super) bots: Option<RawBotsConfig>,
+    pub(super) easy: Option<RawLevelConfig>,
+    pub(super) hard: Option<RawLevelConfig>,
+    pub(super) admin_chat_id: Option<i64>,
+    pub(super) name_rules: Option<Vec<RawNameRule>>,
+    pub(super) raid: Option<RawRaidConfig>,
+    pub(super) risk: Option<RawRiskConfig>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawNameRule {
+    pub(super) pattern: String,
+    pub(super) fields: Option<Vec<RawNameField>>,
+    pub(super) action: RawNameRuleAction,
+    pub(super) federate: Option<bool>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum RawNameField {
+    FirstName,
+    LastName,
+    Username,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum RawNameRuleAction {
+    Ban,
+    Hard,
+    Flag,
+}
+
+/// Overrides question parameters for a challenge level
+#[derive(Deserialize)]
+pub(super) struct RawLevelConfig {
+    pub(super) question: Option<String>,
+    pub(super) buttons: Option<Vec<RawButtonConfig>>,
+    pub(super) response_timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum RawParseMode {
+    Html,
+    Markdown,
+    MarkdownV2,
+    Plain,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawMediaConfig {
+    #[serde(rename = "type")]
+    pub(super) kind: RawMediaKind,
+    pub(super) path: Option<PathBuf>,
+    pub(super) file_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum RawMediaKind {
+    Animation,
+    Photo,
+    Sticker,
 }
 
 #[derive(Deserialize)]
@@ -41,10 +138,93 @@ pub(super) struct RawNotificationConfig {
     pub(super) forbidden: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub(super) struct RawWelcomeConfig {
+    pub(super) text: String,
+    pub(super) delete_after: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawBypassConfig {
+    pub(super) admins: Option<bool>,
+    pub(super) added_by_admin: Option<bool>,
+    pub(super) users: Option<Vec<i64>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawBotsConfig {
+    pub(super) added_by_admin: Option<bool>,
+    pub(super) usernames: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawRejoinConfig {
+    pub(super) limit: u64,
+    pub(super) period: u64,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawRaidConfig {
+    pub(super) joins: u64,
+    pub(super) period: u64,
+    pub(super) lockdown: Option<RawLockdownConfig>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawLockdownConfig {
+    pub(super) action: Option<RawLockdownAction>,
+    pub(super) quiet_period: Option<u64>,
+    pub(super) started_text: Option<String>,
+    pub(super) ended_text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum RawLockdownAction {
+    Kick,
+    Hard,
+}
+
+#[derive(Deserialize)]
+pub(super) struct RawRiskConfig {
+    pub(super) weights: Option<RawRiskWeights>,
+    pub(super) new_account_id: Option<i64>,
+    pub(super) languages: Option<Vec<String>>,
+    pub(super) thresholds: Option<RawRiskThresholds>,
+    pub(super) federate: Option<bool>,
+}
+
+#[derive(Default, Deserialize)]
+pub(super) struct RawRiskWeights {
+    pub(super) name_rule: Option<u64>,
+    pub(super) no_username: Option<u64>,
+    pub(super) no_photo: Option<u64>,
+    pub(super) new_account: Option<u64>,
+    pub(super) language: Option<u64>,
+    pub(super) raid: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+pub(super) struct RawRiskThresholds {
+    pub(super) skip: Option<u64>,
+    pub(super) easy: Option<u64>,
+    pub(super) hard: Option<u64>,
+    pub(super) ban: Option<u64>,
+}
+
 #[derive(Deserialize)]
 pub(super) struct RawActionConfig {
     pub(super) wrong: Option<RawAction>,
     pub(super) timeout: Option<RawAction>,
+    pub(super) restrict_for: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum RawOverflow {
+    Kick,
+    Queue,
+    Batch,
 }
 
 #[derive(Deserialize)]
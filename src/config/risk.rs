@@ -0,0 +1,233 @@
+use crate::config::{
+    chat::{ChallengeLevel, Verification},
+    raw::RawRiskConfig,
+};
+use carapax::types::Integer;
+use std::{error::Error, fmt};
+
+/// Weights of signals and thresholds to choose verification by a risk score
+pub struct RiskConfig {
+    name_rule: u64,
+    no_username: u64,
+    no_photo: u64,
+    new_account: u64,
+    new_account_id: Option<Integer>,
+    language: u64,
+    languages: Vec<String>,
+    raid: u64,
+    skip_below: Option<u64>,
+    easy_below: Option<u64>,
+    hard_from: Option<u64>,
+    ban_from: Option<u64>,
+    federate: bool,
+}
+
+impl RiskConfig {
+    pub(super) fn from_raw(raw: RawRiskConfig) -> Result<Self, RiskConfigError> {
+        let weights = raw.weights.unwrap_or_default();
+        let thresholds = raw.thresholds.unwrap_or_default();
+        // Verification is chosen by the first reached threshold, so a misordered one is never reached
+        let ordered = [
+            ("skip", thresholds.skip),
+            ("easy", thresholds.easy),
+            ("hard", thresholds.hard),
+            ("ban", thresholds.ban),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| (*name, value)))
+        .collect::<Vec<_>>();
+        for pair in ordered.windows(2) {
+            let ((lower, lower_value), (upper, upper_value)) = (pair[0], pair[1]);
+            if lower_value > upper_value {
+                return Err(RiskConfigError::ThresholdOrder { lower, upper });
+            }
+        }
+        Ok(Self {
+            name_rule: weights.name_rule.unwrap_or(0),
+            no_username: weights.no_username.unwrap_or(0),
+            no_photo: weights.no_photo.unwrap_or(0),
+            new_account: weights.new_account.unwrap_or(0),
+            new_account_id: raw.new_account_id,
+            language: weights.language.unwrap_or(0),
+            languages: raw
+                .languages
+                .unwrap_or_default()
+                .into_iter()
+                .map(|x| x.to_lowercase())
+                .collect(),
+            raid: weights.raid.unwrap_or(0),
+            skip_below: thresholds.skip,
+            easy_below: thresholds.easy,
+            hard_from: thresholds.hard,
+            ban_from: thresholds.ban,
+            federate: raw.federate.unwrap_or(false),
+        })
+    }
+
+    /// Returns a weight of a name matching a rule with `flag` action
+    pub fn name_rule(&self) -> u64 {
+        self.name_rule
+    }
+
+    /// Returns a weight of a user without username
+    pub fn no_username(&self) -> u64 {
+        self.no_username
+    }
+
+    /// Returns a weight of a user without profile photos
+    pub fn no_photo(&self) -> u64 {
+        self.no_photo
+    }
+
+    /// Returns a weight of a new account
+    pub fn new_account(&self) -> u64 {
+        self.new_account
+    }
+
+    /// Whether an account is considered as a new one
+    ///
+    /// User IDs grow over time, so IDs greater than configured one belong to recent accounts.
+    pub fn is_new_account(&self, user_id: Integer) -> bool {
+        self.new_account_id.map_or(false, |x| user_id > x)
+    }
+
+    /// Returns a weight of a language not expected in the chat
+    pub fn language(&self) -> u64 {
+        self.language
+    }
+
+    /// Whether a language of a user is not expected in the chat
+    ///
+    /// Unknown language is never considered as a mismatch.
+    pub fn is_language_mismatch(&self, language_code: Option<&str>) -> bool {
+        match language_code {
+            Some(code) if !self.languages.is_empty() => {
+                // Codes may contain a region, e.g. "en-US"
+                let code = code.split('-').next().unwrap_or(code).to_lowercase();
+                !self.languages.contains(&code)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns a weight of a join during a raid
+    pub fn raid(&self) -> u64 {
+        self.raid
+    }
+
+    /// Returns verification for a risk score
+    ///
+    /// Score is a heuristic and weights may be tuned wrong, so bans are local by default.
+    pub fn verification(&self, score: u64) -> Verification {
+        if self.ban_from.map_or(false, |x| score >= x) {
+            Verification::Ban {
+                reason: format!("risk score {}", score),
+                federate: self.federate,
+            }
+        } else if self.hard_from.map_or(false, |x| score >= x) {
+            Verification::Challenge(ChallengeLevel::Hard)
+        } else if self.skip_below.map_or(false, |x| score < x) {
+            Verification::Skip
+        } else if self.easy_below.map_or(false, |x| score < x) {
+            Verification::Challenge(ChallengeLevel::Easy)
+        } else {
+            Verification::Challenge(ChallengeLevel::Normal)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RiskConfigError {
+    ThresholdOrder { lower: &'static str, upper: &'static str },
+}
+
+impl fmt::Display for RiskConfigError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskConfigError::ThresholdOrder { lower, upper } => write!(
+                out,
+                "risk threshold '{}' must not be greater than '{}' threshold",
+                lower, upper
+            ),
+        }
+    }
+}
+
+impl Error for RiskConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::raw::RawRiskThresholds;
+
+    fn from_thresholds(
+        skip: Option<u64>,
+        easy: Option<u64>,
+        hard: Option<u64>,
+        ban: Option<u64>,
+    ) -> Result<RiskConfig, RiskConfigError> {
+        RiskConfig::from_raw(RawRiskConfig {
+            weights: None,
+            new_account_id: None,
+            languages: None,
+            thresholds: Some(RawRiskThresholds { skip, easy, hard, ban }),
+            federate: None,
+        })
+    }
+
+    #[test]
+    fn threshold_order() {
+        assert!(from_thresholds(Some(10), Some(30), Some(50), Some(80)).is_ok());
+        assert!(from_thresholds(None, Some(30), None, Some(30)).is_ok());
+        assert!(from_thresholds(None, None, None, None).is_ok());
+        assert!(matches!(
+            from_thresholds(Some(40), Some(30), Some(50), Some(80)),
+            Err(RiskConfigError::ThresholdOrder {
+                lower: "skip",
+                upper: "easy"
+            })
+        ));
+        assert!(matches!(
+            from_thresholds(None, Some(60), None, Some(50)),
+            Err(RiskConfigError::ThresholdOrder {
+                lower: "easy",
+                upper: "ban"
+            })
+        ));
+        assert!(matches!(
+            from_thresholds(None, None, Some(90), Some(80)),
+            Err(RiskConfigError::ThresholdOrder {
+                lower: "hard",
+                upper: "ban"
+            })
+        ));
+    }
+
+    #[test]
+    fn verification() {
+        let risk = from_thresholds(Some(10), Some(30), Some(50), Some(80)).unwrap();
+        assert!(matches!(risk.verification(0), Verification::Skip));
+        assert!(matches!(risk.verification(9), Verification::Skip));
+        assert!(matches!(
+            risk.verification(10),
+            Verification::Challenge(ChallengeLevel::Easy)
+        ));
+        assert!(matches!(
+            risk.verification(30),
+            Verification::Challenge(ChallengeLevel::Normal)
+        ));
+        assert!(matches!(
+            risk.verification(50),
+            Verification::Challenge(ChallengeLevel::Hard)
+        ));
+        assert!(matches!(
+            risk.verification(80),
+            Verification::Ban { federate: false, .. }
+        ));
+        let risk = from_thresholds(None, None, None, None).unwrap();
+        assert!(matches!(
+            risk.verification(1000),
+            Verification::Challenge(ChallengeLevel::Normal)
+        ));
+    }
+}
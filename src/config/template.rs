@@ -0,0 +1,324 @@
+use carapax::types::{Integer, Message, MessageKind, ParseMode, User};
+use chrono::{FixedOffset, TimeZone};
+use liquid::{
+    error::Result as LiquidResult,
+    value::{liquid_value, Object, PathRef, Scalar, Value},
+    Error as TemplateError, Template, ValueStore,
+};
+use std::{error::Error, fmt, iter, time::Duration};
+
+/// Information about a chat available in templates
+#[derive(Clone, Copy, Debug)]
+pub struct ChatInfo<'a> {
+    pub id: Integer,
+    pub title: Option<&'a str>,
+    pub username: Option<&'a str>,
+}
+
+impl<'a> ChatInfo<'a> {
+    pub fn from_message(message: &'a Message) -> Self {
+        let (title, username) = match message.kind {
+            MessageKind::Channel { ref chat, .. } => (Some(chat.title.as_str()), chat.username.as_deref()),
+            MessageKind::Group { ref chat, .. } => (Some(chat.title.as_str()), None),
+            MessageKind::Private { ref chat, .. } => (None, chat.username.as_deref()),
+            MessageKind::Supergroup { ref chat, .. } => (Some(chat.title.as_str()), chat.username.as_deref()),
+        };
+        Self {
+            id: message.get_chat_id(),
+            title,
+            username,
+        }
+    }
+}
+
+/// Variables available in question and welcome templates
+pub struct QuestionVars<'a> {
+    /// A new chat member
+    pub user: &'a User,
+    /// Other users asked by the same batch question
+    ///
+    /// They are available in `users` after the first user.
+    pub batch: &'a [User],
+    pub chat: ChatInfo<'a>,
+    /// Unix time when user joined the chat
+    pub joined_at: Integer,
+    /// Time left before question will be deleted
+    pub remaining: Duration,
+}
+
+impl<'a> QuestionVars<'a> {
+    pub(super) fn to_object(
+        &self,
+        parse_mode: Option<ParseMode>,
+        timeout: Duration,
+        buttons_count: usize,
+    ) -> Result<Object, RenderTemplateError> {
+        let users = iter::once(self.user)
+            .chain(self.batch)
+            .map(|user| user_value(user, get_mention(user, parse_mode), parse_mode))
+            .collect();
+        let joined_at = FixedOffset::east(0).timestamp(self.joined_at, 0);
+        liquid_value!({
+            "user": user_value(self.user, get_mention(self.user, parse_mode), parse_mode),
+            "users": Value::Array(users),
+            "chat": chat_value(&self.chat, parse_mode),
+            "timeout": timeout.as_secs(),
+            "timeout_human": humanize_duration(timeout),
+            "buttons_count": buttons_count,
+            "joined_at": Value::Scalar(Scalar::new(joined_at)),
+            "remaining": self.remaining.as_secs(),
+        })
+        .into_object()
+        .ok_or(RenderTemplateError::CreateVariables)
+    }
+}
+
+/// Variables available in lockdown templates
+pub struct LockdownVars<'a> {
+    pub chat: ChatInfo<'a>,
+    /// Number of users joined within raid period when lockdown starts and during lockdown when it ends
+    pub joins: usize,
+}
+
+impl<'a> LockdownVars<'a> {
+    pub(super) fn to_object(&self, parse_mode: Option<ParseMode>) -> Result<Object, RenderTemplateError> {
+        liquid_value!({
+            "chat": chat_value(&self.chat, parse_mode),
+            "joins": self.joins,
+        })
+        .into_object()
+        .ok_or(RenderTemplateError::CreateVariables)
+    }
+}
+
+/// Variables available in notification templates
+pub struct NotificationVars<'a> {
+    /// A user the question is addressed to
+    pub user: &'a User,
+    pub chat: ChatInfo<'a>,
+    pub remaining: Duration,
+}
+
+impl<'a> NotificationVars<'a> {
+    pub(super) fn to_object(&self) -> Result<Object, RenderTemplateError> {
+        // Notifications are shown as plain text, so mention is just a name of the user
+        liquid_value!({
+            "user": user_value(self.user, self.user.get_full_name(), None),
+            "chat": chat_value(&self.chat, None),
+            "remaining": self.remaining.as_secs(),
+        })
+        .into_object()
+        .ok_or(RenderTemplateError::CreateVariables)
+    }
+}
+
+fn get_mention(user: &User, parse_mode: Option<ParseMode>) -> String {
+    let name = user.get_full_name();
+    match parse_mode {
+        Some(ParseMode::Html) => format!(r#"<a href="{}">{}</a>"#, user.get_link(), ParseMode::Html.escape(name)),
+        Some(parse_mode) => format!("[{}]({})", parse_mode.escape(name), user.get_link()),
+        None => name,
+    }
+}
+
+// IDs are passed as strings since liquid can not store integers larger than i32
+fn user_value(user: &User, mention: String, parse_mode: Option<ParseMode>) -> Value {
+    liquid_value!({
+        "id": user.id.to_string(),
+        "first_name": escape(&user.first_name, parse_mode),
+        "last_name": user.last_name.as_deref().map(|x| escape(x, parse_mode)),
+        "username": user.username.as_deref().map(|x| escape(x, parse_mode)),
+        "name": escape(&user.get_full_name(), parse_mode),
+        "mention": mention,
+    })
+}
+
+fn chat_value(chat: &ChatInfo, parse_mode: Option<ParseMode>) -> Value {
+    liquid_value!({
+        "id": chat.id.to_string(),
+        "title": chat.title.map(|x| escape(x, parse_mode)),
+        "username": chat.username.map(|x| escape(x, parse_mode)),
+    })
+}
+
+/// Names are set by users, so they must not break markup of a message
+fn escape(value: &str, parse_mode: Option<ParseMode>) -> String {
+    match parse_mode {
+        Some(parse_mode) => parse_mode.escape(value),
+        None => String::from(value),
+    }
+}
+
+fn humanize_duration(duration: Duration) -> String {
+    let total = duration.as_secs();
+    let units = [
+        (total / 3600, "hour"),
+        (total % 3600 / 60, "minute"),
+        (total % 60, "second"),
+    ];
+    let parts: Vec<String> = units
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{} {}{}", value, unit, if *value == 1 { "" } else { "s" }))
+        .collect();
+    if parts.is_empty() {
+        String::from("0 seconds")
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Variables where `user` itself is an alias for `user.mention`
+///
+/// Templates written before user became an object use `{{user}}` for a mention,
+/// so the alias works with filters and any spacing.
+#[derive(Debug)]
+struct Variables<'a> {
+    object: &'a Object,
+    mention: Option<&'a Value>,
+}
+
+impl<'a> Variables<'a> {
+    fn new(object: &'a Object) -> Self {
+        let mention = object
+            .get("user")
+            .and_then(Value::as_object)
+            .and_then(|user| user.get("mention"));
+        Self { object, mention }
+    }
+
+    fn get_alias(&self, path: PathRef) -> Option<&'a Value> {
+        match path {
+            [name] if name.to_str() == "user" => self.mention,
+            _ => None,
+        }
+    }
+}
+
+impl<'a> ValueStore for Variables<'a> {
+    fn contains_root(&self, name: &str) -> bool {
+        self.object.contains_root(name)
+    }
+
+    fn roots(&self) -> Vec<&str> {
+        self.object.roots()
+    }
+
+    fn contains_variable(&self, path: PathRef) -> bool {
+        self.get_alias(path).is_some() || self.object.contains_variable(path)
+    }
+
+    fn try_get_variable<'b>(&'b self, path: PathRef) -> Option<&'b Value> {
+        self.get_alias(path).or_else(|| self.object.try_get_variable(path))
+    }
+
+    fn get_variable<'b>(&'b self, path: PathRef) -> LiquidResult<&'b Value> {
+        match self.get_alias(path) {
+            Some(value) => Ok(value),
+            None => self.object.get_variable(path),
+        }
+    }
+}
+
+pub(super) fn render(template: &Template, vars: &Object) -> Result<String, RenderTemplateError> {
+    Ok(template
+        .render(&Variables::new(vars))
+        .map_err(RenderTemplateError::Render)?
+        .trim()
+        .to_string())
+}
+
+#[derive(Debug)]
+pub enum RenderTemplateError {
+    CreateVariables,
+    Render(TemplateError),
+}
+
+impl fmt::Display for RenderTemplateError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::RenderTemplateError::*;
+        match self {
+            CreateVariables => write!(out, "could not create variables for template"),
+            Render(err) => write!(out, "failed to render template: {}", err),
+        }
+    }
+}
+
+impl Error for RenderTemplateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::RenderTemplateError::*;
+        match self {
+            CreateVariables => None,
+            Render(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use liquid::ParserBuilder;
+
+    fn create_user(id: Integer, first_name: &str) -> User {
+        User {
+            id,
+            is_bot: false,
+            first_name: String::from(first_name),
+            last_name: None,
+            username: None,
+            language_code: None,
+        }
+    }
+
+    fn render_source(source: &str) -> String {
+        let parser = ParserBuilder::with_liquid().build().unwrap();
+        let template = parser.parse(source).unwrap();
+        let user = create_user(1, "John");
+        let batch = [create_user(2, "Jane")];
+        let vars = QuestionVars {
+            user: &user,
+            batch: &batch,
+            chat: ChatInfo {
+                id: 1,
+                title: None,
+                username: None,
+            },
+            joined_at: 0,
+            remaining: Duration::from_secs(1),
+        };
+        render(&template, &vars.to_object(None, Duration::from_secs(1), 1).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn user_is_alias_for_mention() {
+        assert_eq!(render_source("{{user}}"), "John");
+        assert_eq!(render_source("{{  user }}"), "John");
+        assert_eq!(render_source("{{ user | upcase }}"), "JOHN");
+        assert_eq!(render_source("{{ user.first_name }} {{ user.id }}"), "John 1");
+    }
+
+    #[test]
+    fn users_contain_batch() {
+        assert_eq!(render_source("{{ user.mention }}"), "John");
+        assert_eq!(
+            render_source("{% for u in users %}{{ u.mention }} {% endfor %}"),
+            "John Jane"
+        );
+    }
+
+    #[test]
+    fn lockdown_vars_are_escaped() {
+        let parser = ParserBuilder::with_liquid().build().unwrap();
+        let template = parser.parse("{{ chat.title }}: {{ joins }}").unwrap();
+        let vars = LockdownVars {
+            chat: ChatInfo {
+                id: 1,
+                title: Some("<Chat>"),
+                username: None,
+            },
+            joins: 3,
+        };
+        let object = vars.to_object(Some(ParseMode::Html)).unwrap();
+        assert_eq!(render(&template, &object).unwrap(), "&lt;Chat&gt;: 3");
+    }
+}
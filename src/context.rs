@@ -1,21 +1,54 @@
-use crate::config::ChatConfig;
+use crate::{
+    api::LimitedApi,
+    batch::BatchQueue,
+    blocklist::Blocklist,
+    challenge::ChallengeRegistry,
+    config::ChatConfig,
+    joins::JoinTracker,
+    lockdown::LockdownRegistry,
+    scheduler::Scheduler,
+    store::{Store, UploadedFile},
+};
 use carapax::{
     session::{backend::fs::FilesystemBackend, SessionManager},
-    types::Integer,
-    Api,
+    types::{ChatPermissions, Integer},
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// A key to store ID of a media message sent separately from question
+pub const MEDIA_MESSAGE_SESSION_KEY: &str = "media_message";
 
+#[derive(Clone)]
 pub struct Context {
-    pub api: Api,
-    pub chats: HashMap<Integer, ChatConfig>,
+    pub api: LimitedApi,
+    pub chats: Arc<HashMap<Integer, Arc<ChatConfig>>>,
     pub session_manager: SessionManager<FilesystemBackend>,
+    pub store: Store,
+    pub scheduler: Scheduler,
+    pub challenges: ChallengeRegistry,
+    pub blocklist: Option<Blocklist>,
+    pub joins: JoinTracker,
+    pub lockdowns: LockdownRegistry,
+    pub batches: BatchQueue,
+    /// Uploaded media files by path, saved in store
+    pub uploaded_files: Arc<Mutex<HashMap<PathBuf, UploadedFile>>>,
+    /// Default permissions of chats and time when they were obtained
+    pub default_permissions: Arc<Mutex<HashMap<Integer, (Instant, ChatPermissions)>>>,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 pub struct Payload {
     pub chat_id: Integer,
-    pub user_id: Integer,
+    /// A user the question is addressed to
+    ///
+    /// Not set for a batch question, it is answered by any user asked by the question.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<Integer>,
     pub is_right: bool,
 }
@@ -0,0 +1,30 @@
+use crate::{
+    api::{Priority, Target},
+    config::ChatConfig,
+    context::Context,
+};
+use carapax::{methods::SendMessage, types::Integer};
+
+/// Notifies admins of the chat
+///
+/// Alert is only logged when admin chat is not configured.
+pub(super) async fn send(context: &Context, config: &ChatConfig, chat_id: Integer, text: String) {
+    let admin_chat_id = match config.admin_chat_id() {
+        Some(admin_chat_id) => admin_chat_id,
+        None => {
+            log::warn!("Alert (chat_id={}): {}", chat_id, text);
+            return;
+        }
+    };
+    if let Err(err) = context
+        .api
+        .execute_with(
+            Priority::Normal,
+            Target::message(admin_chat_id),
+            SendMessage::new(admin_chat_id, text),
+        )
+        .await
+    {
+        log::warn!("Failed to send alert to chat {}: {}", admin_chat_id, err);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    api::{Priority, Target},
+    config::ChatConfig,
+    context::Context,
+    handler::{error::HandlerError, unix_time},
+    store::FederatedBan,
+};
+use carapax::{methods::KickChatMember, types::Integer};
+use std::time::Duration;
+
+/// Telegram bans a user forever when a kick is shorter than 30 seconds
+const MIN_KICK_PERIOD: Duration = Duration::from_secs(30);
+
+/// Kicks a user from the chat for a period
+///
+/// User is not trusted in the chat network anymore, but the kick is never federated.
+pub(super) async fn kick(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    user_id: Integer,
+    period: Duration,
+    reason: &str,
+) -> Result<(), HandlerError> {
+    let until_date = unix_time() + period.max(MIN_KICK_PERIOD).as_secs();
+    context
+        .api
+        .execute_with(
+            Priority::High,
+            Target::member(chat_id, user_id),
+            KickChatMember::new(chat_id, user_id).until_date(until_date as Integer),
+        )
+        .await?;
+    log::info!("User kicked (chat_id={}, user_id={}): {}", chat_id, user_id, reason);
+    distrust(context, config, user_id).await
+}
+
+/// Bans a user in the chat
+///
+/// User is not trusted in the chat network anymore.
+/// When network has federated bans and `federate` is set, user is banned in all chats of the network.
+/// Bans in other chats of the network last for `ban_for` when it is set.
+pub(super) async fn ban(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    user_id: Integer,
+    reason: &str,
+    federate: bool,
+) -> Result<(), HandlerError> {
+    context
+        .api
+        .execute_with(
+            Priority::High,
+            Target::member(chat_id, user_id),
+            KickChatMember::new(chat_id, user_id),
+        )
+        .await?;
+    log::info!("User banned (chat_id={}, user_id={}): {}", chat_id, user_id, reason);
+    distrust(context, config, user_id).await?;
+    let network = match config.network() {
+        Some(network) => network,
+        None => return Ok(()),
+    };
+    if !federate || !network.federated_bans() {
+        return Ok(());
+    }
+    let now = unix_time();
+    let is_banned = context
+        .store
+        .load_ban(network.name(), user_id)
+        .await
+        .map_err(HandlerError::LoadBan)?
+        .map_or(false, |x| !x.is_expired(network.ban_for(), now));
+    if is_banned {
+        // Ban is propagated already
+        return Ok(());
+    }
+    let federated_ban = FederatedBan {
+        chat_id,
+        reason: String::from(reason),
+        banned_at: now,
+    };
+    context
+        .store
+        .save_ban(network.name(), user_id, &federated_ban)
+        .await
+        .map_err(HandlerError::SaveBan)?;
+    for other_chat_id in network.chats().iter().filter(|x| **x != chat_id) {
+        let mut method = KickChatMember::new(*other_chat_id, user_id);
+        if let Some(ban_for) = network.ban_for() {
+            method = method.until_date((now + ban_for.as_secs()) as Integer);
+        }
+        match context
+            .api
+            .execute_with(Priority::High, Target::member(*other_chat_id, user_id), method)
+            .await
+        {
+            Ok(_) => log::info!(
+                "User banned in network '{}' (chat_id={}, user_id={})",
+                network.name(),
+                other_chat_id,
+                user_id
+            ),
+            Err(err) => log::warn!(
+                "Failed to ban user in network '{}' (chat_id={}, user_id={}): {}",
+                network.name(),
+                other_chat_id,
+                user_id,
+                err
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Removes a user from trusted users of the chat network
+///
+/// Called whenever a user is punished in a chat: kicked, restricted or banned.
+pub(super) async fn distrust(context: &Context, config: &ChatConfig, user_id: Integer) -> Result<(), HandlerError> {
+    if let Some(network) = config.network() {
+        context
+            .store
+            .remove_trusted(network.name(), user_id)
+            .await
+            .map_err(HandlerError::RemoveTrusted)?;
+    }
+    Ok(())
+}
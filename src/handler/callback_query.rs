@@ -1,59 +1,223 @@
 use crate::{
-    config::{Action, DEFAULT_NOTIFICATION_FORBIDDEN},
-    context::{Context, Payload},
-    handler::error::HandlerError,
-    permissions::PERMISSIONS_SESSION_KEY,
+    api::{Priority, Target},
+    challenge::ChallengeState,
+    config::{
+        Action, ChallengeLevel, ChatConfig, ChatInfo, NotificationVars, QuestionVars, DEFAULT_NOTIFICATION_FORBIDDEN,
+    },
+    context::{Context, Payload, MEDIA_MESSAGE_SESSION_KEY},
+    handler::{ban, error::HandlerError, question, restriction, unix_time},
+    scheduler::{Job, JobKey},
 };
 use carapax::{
     handler,
-    methods::{AnswerCallbackQuery, DeleteMessage, KickChatMember, RestrictChatMember},
+    methods::{AnswerCallbackQuery, DeleteMessage, GetChatMember, SendMessage},
     session::SessionId,
-    types::{CallbackQuery, ChatPermissions},
+    types::{CallbackQuery, Integer, Message, MessageData, User},
 };
+use std::time::Duration;
 
 #[handler]
 pub async fn handle(context: &Context, query: CallbackQuery) -> Result<(), HandlerError> {
-    let answer = if let Ok(Some(data)) = query.parse_data::<Payload>() {
-        let config = match context.chats.get(&data.chat_id) {
-            Some(config) => config,
-            None => return Ok(()),
+    let data = match query.parse_data::<Payload>() {
+        Ok(Some(data)) => data,
+        _ => {
+            context
+                .api
+                .execute(AnswerCallbackQuery::new(query.id).text(DEFAULT_NOTIFICATION_FORBIDDEN))
+                .await?;
+            return Ok(());
+        }
+    };
+    let config = match context.chats.get(&data.chat_id) {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let chat = match query.message {
+        Some(ref message) => ChatInfo::from_message(message),
+        None => ChatInfo {
+            id: data.chat_id,
+            title: None,
+            username: None,
+        },
+    };
+    // Any user asked by a batch question can press its buttons
+    let is_batch = data.user_id.is_none();
+    let user_id = data.user_id.unwrap_or(query.from.id);
+    let challenge = context
+        .store
+        .load_challenge(data.chat_id, user_id)
+        .await
+        .map_err(HandlerError::LoadChallenge)?;
+    if is_batch && challenge.as_ref().and_then(|x| x.message_id) != query.message.as_ref().map(|x| x.id) {
+        context
+            .api
+            .execute(AnswerCallbackQuery::new(query.id).text(DEFAULT_NOTIFICATION_FORBIDDEN))
+            .await?;
+        return Ok(());
+    }
+    let level = challenge.as_ref().map(|x| x.level).unwrap_or_default();
+    let remaining = match challenge {
+        Some(ref challenge) => Duration::from_secs(challenge.expires_at.saturating_sub(unix_time())),
+        None => query
+            .message
+            .as_ref()
+            .map(|message| get_remaining_time(message, config.response_timeout(level)))
+            .unwrap_or_else(|| config.response_timeout(level)),
+    };
+    let answer = if user_id == query.from.id {
+        if context.challenges.get(data.chat_id, user_id) != Some(ChallengeState::Asked) {
+            // Question is expired or answered already
+            context.api.execute(AnswerCallbackQuery::new(query.id)).await?;
+            return Ok(());
+        }
+        let vars = NotificationVars {
+            user: &query.from,
+            chat,
+            remaining,
         };
-        if data.user_id == query.from.id {
-            if let Some(message) = query.message {
-                match context.api.execute(DeleteMessage::new(data.chat_id, message.id)).await {
-                    Ok(_) => log::info!("Question #{} successfully deleted", message.id),
-                    Err(err) => log::warn!("Failed to delete question: {}", err),
-                };
-            }
-            if data.is_right {
-                let mut session = context
+        let outcome = if data.is_right {
+            ChallengeState::Passed
+        } else {
+            ChallengeState::Failed
+        };
+        if !context
+            .challenges
+            .transition(data.chat_id, user_id, ChallengeState::Asked, outcome)
+        {
+            // Timeout handler took the challenge first
+            context.api.execute(AnswerCallbackQuery::new(query.id)).await?;
+            return Ok(());
+        }
+        context
+            .store
+            .remove_challenge(data.chat_id, user_id)
+            .await
+            .map_err(HandlerError::RemoveChallenge)?;
+        context
+            .scheduler
+            .cancel(JobKey::expire_challenge(data.chat_id, user_id));
+        context.scheduler.cancel(JobKey::update_question(data.chat_id, user_id));
+        match challenge {
+            Some(ref challenge) => question::delete(context, challenge).await,
+            None => {
+                if let Some(ref message) = query.message {
+                    match context
+                        .api
+                        .execute_with(
+                            Priority::Low,
+                            Target::chat(data.chat_id),
+                            DeleteMessage::new(data.chat_id, message.id),
+                        )
+                        .await
+                    {
+                        Ok(_) => log::info!("Question #{} successfully deleted", message.id),
+                        Err(err) => log::warn!("Failed to delete question: {}", err),
+                    };
+                }
+                let media_message_id: Option<Integer> = context
                     .session_manager
-                    .get_session(SessionId::new(data.chat_id, data.user_id))
-                    .expect("Failed to get session"); // Should never panic as we provided SessionId
-                let permissions = session
-                    .get(PERMISSIONS_SESSION_KEY)
+                    .get_session(SessionId::new(data.chat_id, user_id))
+                    .expect("Failed to get session") // Should never panic as we provided SessionId
+                    .get(MEDIA_MESSAGE_SESSION_KEY)
                     .await
-                    .map_err(HandlerError::LoadPermissions)?
-                    .unwrap_or_else(ChatPermissions::allowed);
-                context
-                    .api
-                    .execute(RestrictChatMember::new(data.chat_id, data.user_id).with_permissions(permissions))
-                    .await?;
-                config.notification_right()
-            } else {
-                if let Action::Kick = config.action_wrong() {
-                    context
+                    .map_err(HandlerError::LoadMediaMessage)?;
+                if let Some(media_message_id) = media_message_id {
+                    match context
                         .api
-                        .execute(KickChatMember::new(data.chat_id, data.user_id))
-                        .await?;
+                        .execute_with(
+                            Priority::Low,
+                            Target::chat(data.chat_id),
+                            DeleteMessage::new(data.chat_id, media_message_id),
+                        )
+                        .await
+                    {
+                        Ok(_) => log::info!("Media message #{} successfully deleted", media_message_id),
+                        Err(err) => log::warn!("Failed to delete media message: {}", err),
+                    };
                 }
-                config.notification_wrong()
             }
+        }
+        if data.is_right {
+            restriction::restore(context, data.chat_id, user_id).await?;
+            if let Some(network) = config.network() {
+                context
+                    .store
+                    .add_trusted(network.name(), user_id, unix_time())
+                    .await
+                    .map_err(HandlerError::SaveTrusted)?;
+            }
+            // Question may be sent long after join, e.g. for a batch or after ask timeout
+            let joined_at = match challenge {
+                Some(ref challenge) => challenge.joined_at,
+                None => query
+                    .message
+                    .as_ref()
+                    .map(|x| x.reply_to.as_ref().map(|x| x.date).unwrap_or(x.date))
+                    .unwrap_or(0),
+            };
+            let welcome_vars = QuestionVars {
+                user: &query.from,
+                batch: &[],
+                chat,
+                joined_at,
+                remaining,
+            };
+            // User is verified already, query must be answered anyway
+            if let Err(err) = send_welcome(context, config, data.chat_id, level, &welcome_vars).await {
+                log::error!(
+                    "Failed to send a welcome message (chat_id={}, user_id={}): {}",
+                    data.chat_id,
+                    user_id,
+                    err
+                );
+            }
+            config.render_notification_right(&vars)?
         } else {
-            config.notification_forbidden()
+            match config.action_wrong() {
+                Action::Kick => ban::ban(context, config, data.chat_id, user_id, "wrong answer", true).await?,
+                Action::Restrict => {
+                    ban::distrust(context, config, user_id).await?;
+                    if let Some(restrict_for) = config.restrict_for() {
+                        context.scheduler.schedule(
+                            restrict_for,
+                            Job::LiftMute {
+                                chat_id: data.chat_id,
+                                user_id,
+                            },
+                        );
+                    }
+                }
+            }
+            config.render_notification_wrong(&vars)?
         }
     } else {
-        DEFAULT_NOTIFICATION_FORBIDDEN
+        let user = match query.message.as_ref().and_then(|x| find_new_chat_member(x, user_id)) {
+            Some(user) => Some(user.clone()),
+            None => match context
+                .api
+                .execute_with(
+                    Priority::Normal,
+                    Target::member(data.chat_id, user_id),
+                    GetChatMember::new(data.chat_id, user_id),
+                )
+                .await
+            {
+                Ok(member) => Some(member.get_user().clone()),
+                Err(err) => {
+                    log::warn!("Failed to get a user the question is addressed to: {}", err);
+                    None
+                }
+            },
+        };
+        match user {
+            Some(user) => config.render_notification_forbidden(&NotificationVars {
+                user: &user,
+                chat,
+                remaining,
+            })?,
+            // Query must be answered anyway, otherwise button keeps spinning
+            None => String::from(DEFAULT_NOTIFICATION_FORBIDDEN),
+        }
     };
     context
         .api
@@ -61,3 +225,50 @@ pub async fn handle(context: &Context, query: CallbackQuery) -> Result<(), Handl
         .await?;
     Ok(())
 }
+
+/// Sends a welcome message to a user who answered right, if configured
+async fn send_welcome(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    level: ChallengeLevel,
+    vars: &QuestionVars<'_>,
+) -> Result<(), HandlerError> {
+    let welcome = match config.render_welcome(level, vars)? {
+        Some(welcome) => welcome,
+        None => return Ok(()),
+    };
+    let mut method = SendMessage::new(chat_id, welcome.replace("\\n", "\n"));
+    if let Some(parse_mode) = config.parse_mode() {
+        method = method.parse_mode(parse_mode);
+    }
+    let message = context
+        .api
+        .execute_with(Priority::Normal, Target::message(chat_id), method)
+        .await?;
+    if let Some(timeout) = config.welcome_delete_after() {
+        context.scheduler.schedule(
+            timeout,
+            Job::DeleteMessage {
+                chat_id,
+                message_id: message.id,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Returns a user the question is addressed to
+///
+/// Question is always sent as a reply to the new chat members message
+fn find_new_chat_member(question: &Message, user_id: Integer) -> Option<&User> {
+    match question.reply_to.as_ref().map(|x| &x.data) {
+        Some(MessageData::NewChatMembers(users)) => users.iter().find(|x| x.id == user_id),
+        _ => None,
+    }
+}
+
+fn get_remaining_time(question: &Message, response_timeout: Duration) -> Duration {
+    let expires_at = (question.date as u64).saturating_add(response_timeout.as_secs());
+    Duration::from_secs(expires_at.saturating_sub(unix_time()))
+}
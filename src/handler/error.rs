@@ -1,14 +1,35 @@
-use crate::config::RenderQuestionError;
-use carapax::{session::SessionError, types::InlineKeyboardError, ExecuteError};
-use std::{error::Error, fmt};
+use crate::{config::RenderTemplateError, store::StoreError};
+use carapax::{
+    session::SessionError,
+    types::{InlineKeyboardError, ReplyMarkupError},
+    ExecuteError,
+};
+use std::{error::Error, fmt, io::Error as IoError};
 
 #[derive(Debug)]
 pub enum HandlerError {
     Execute(ExecuteError),
     InlineKeyboard(InlineKeyboardError),
-    LoadPermissions(SessionError),
-    RenderQuestion(RenderQuestionError),
-    SavePermissions(SessionError),
+    LoadBan(StoreError),
+    LoadChallenge(StoreError),
+    LoadLeaves(StoreError),
+    LoadMediaMessage(SessionError),
+    LoadMemberStatus(StoreError),
+    LoadTrusted(StoreError),
+    OpenMedia(IoError),
+    RemoveBan(StoreError),
+    RemoveChallenge(StoreError),
+    RemoveLeaves(StoreError),
+    RemoveMemberStatus(StoreError),
+    RemoveTrusted(StoreError),
+    RenderTemplate(RenderTemplateError),
+    ReplyMarkup(ReplyMarkupError),
+    SaveBan(StoreError),
+    SaveChallenge(StoreError),
+    SaveLeaves(StoreError),
+    SaveMediaMessage(SessionError),
+    SaveMemberStatus(StoreError),
+    SaveTrusted(StoreError),
 }
 
 impl From<ExecuteError> for HandlerError {
@@ -23,9 +44,15 @@ impl From<InlineKeyboardError> for HandlerError {
     }
 }
 
-impl From<RenderQuestionError> for HandlerError {
-    fn from(err: RenderQuestionError) -> Self {
-        HandlerError::RenderQuestion(err)
+impl From<ReplyMarkupError> for HandlerError {
+    fn from(err: ReplyMarkupError) -> Self {
+        HandlerError::ReplyMarkup(err)
+    }
+}
+
+impl From<RenderTemplateError> for HandlerError {
+    fn from(err: RenderTemplateError) -> Self {
+        HandlerError::RenderTemplate(err)
     }
 }
 
@@ -35,9 +62,26 @@ impl fmt::Display for HandlerError {
         match self {
             Execute(err) => write!(out, "failed to execute method: {}", err),
             InlineKeyboard(err) => write!(out, "can not build inline keyboard: {}", err),
-            LoadPermissions(err) => write!(out, "can not save chat member permissions: {}", err),
-            RenderQuestion(err) => write!(out, "{}", err),
-            SavePermissions(err) => write!(out, "can not save chat member permissions: {}", err),
+            LoadBan(err) => write!(out, "can not load federated ban: {}", err),
+            LoadChallenge(err) => write!(out, "can not load pending challenge: {}", err),
+            LoadLeaves(err) => write!(out, "can not load leaves history: {}", err),
+            LoadMediaMessage(err) => write!(out, "can not load media message ID: {}", err),
+            LoadMemberStatus(err) => write!(out, "can not load chat member status: {}", err),
+            LoadTrusted(err) => write!(out, "can not load trusted user: {}", err),
+            OpenMedia(err) => write!(out, "can not open media file: {}", err),
+            RemoveBan(err) => write!(out, "can not remove federated ban: {}", err),
+            RemoveChallenge(err) => write!(out, "can not remove pending challenge: {}", err),
+            RemoveLeaves(err) => write!(out, "can not remove leaves history: {}", err),
+            RemoveMemberStatus(err) => write!(out, "can not remove chat member status: {}", err),
+            RemoveTrusted(err) => write!(out, "can not remove trusted user: {}", err),
+            RenderTemplate(err) => write!(out, "{}", err),
+            ReplyMarkup(err) => write!(out, "can not build reply markup: {}", err),
+            SaveBan(err) => write!(out, "can not save federated ban: {}", err),
+            SaveChallenge(err) => write!(out, "can not save pending challenge: {}", err),
+            SaveLeaves(err) => write!(out, "can not save leaves history: {}", err),
+            SaveMediaMessage(err) => write!(out, "can not save media message ID: {}", err),
+            SaveMemberStatus(err) => write!(out, "can not save chat member status: {}", err),
+            SaveTrusted(err) => write!(out, "can not save trusted user: {}", err),
         }
     }
 }
@@ -48,9 +92,26 @@ impl Error for HandlerError {
         Some(match self {
             Execute(err) => err,
             InlineKeyboard(err) => err,
-            LoadPermissions(err) => err,
-            RenderQuestion(err) => err,
-            SavePermissions(err) => err,
+            LoadBan(err) => err,
+            LoadChallenge(err) => err,
+            LoadLeaves(err) => err,
+            LoadMediaMessage(err) => err,
+            LoadMemberStatus(err) => err,
+            LoadTrusted(err) => err,
+            OpenMedia(err) => err,
+            RemoveBan(err) => err,
+            RemoveChallenge(err) => err,
+            RemoveLeaves(err) => err,
+            RemoveMemberStatus(err) => err,
+            RemoveTrusted(err) => err,
+            RenderTemplate(err) => err,
+            ReplyMarkup(err) => err,
+            SaveBan(err) => err,
+            SaveChallenge(err) => err,
+            SaveLeaves(err) => err,
+            SaveMediaMessage(err) => err,
+            SaveMemberStatus(err) => err,
+            SaveTrusted(err) => err,
         })
     }
 }
@@ -0,0 +1,197 @@
+use crate::{
+    api::{Priority, Target},
+    challenge::ChallengeState,
+    config::{Action, ChatInfo, QuestionVars},
+    context::Context,
+    handler::{ban, error::HandlerError, lockdown, new_chat_member, question, restriction, unix_time},
+    scheduler::{Job, JobKey},
+};
+use carapax::{
+    methods::DeleteMessage,
+    types::{Integer, User},
+    ExecuteError,
+};
+use std::time::Duration;
+
+/// Executes a scheduled job
+pub async fn execute(context: Context, job: Job) {
+    let key = job.key();
+    let result = match job {
+        Job::SendQuestion(request) => match context.chats.get(&request.chat_id) {
+            Some(config) => new_chat_member::ask(&context, config, request).await,
+            None => {
+                log::info!("Config not found for chat '{}'", request.chat_id);
+                Ok(())
+            }
+        },
+        Job::SendBatch {
+            chat_id,
+            batch_id,
+            requests,
+        } => {
+            context.batches.remove(chat_id, batch_id);
+            match context.chats.get(&chat_id) {
+                Some(config) => new_chat_member::ask_batch(&context, config, requests).await,
+                None => log::info!("Config not found for chat '{}'", chat_id),
+            }
+            Ok(())
+        }
+        Job::UpdateQuestion { chat_id, user_id } => update_question(&context, chat_id, user_id).await,
+        Job::ExpireChallenge { chat_id, user_id } => expire_challenge(&context, chat_id, user_id).await,
+        Job::LiftMute { chat_id, user_id } => lift_mute(&context, chat_id, user_id).await,
+        Job::DeleteMessage { chat_id, message_id } => {
+            match context
+                .api
+                .execute_with(
+                    Priority::Low,
+                    Target::chat(chat_id),
+                    DeleteMessage::new(chat_id, message_id),
+                )
+                .await
+            {
+                Ok(_) => log::info!("Message #{} successfully deleted", message_id),
+                Err(err) => log::warn!("Failed to delete message #{}: {}", message_id, err),
+            }
+            Ok(())
+        }
+        Job::EndLockdown { chat_id } => lockdown::end(&context, chat_id).await,
+        Job::ForgetLeaves { chat_id, user_id } => context
+            .store
+            .remove_leaves(chat_id, user_id)
+            .await
+            .map_err(HandlerError::RemoveLeaves),
+    };
+    if let Err(err) = result {
+        log::error!("Job {} failed: {}", key, err);
+    }
+}
+
+/// Updates remaining time in the question
+async fn update_question(context: &Context, chat_id: Integer, user_id: Integer) -> Result<(), HandlerError> {
+    let (config, interval) = match context.chats.get(&chat_id) {
+        Some(config) => match config.countdown_interval() {
+            Some(interval) => (config, interval),
+            None => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+    if context.challenges.get(chat_id, user_id) != Some(ChallengeState::Asked) {
+        // Question is answered or expired
+        return Ok(());
+    }
+    let challenge = match context
+        .store
+        .load_challenge(chat_id, user_id)
+        .await
+        .map_err(HandlerError::LoadChallenge)?
+    {
+        Some(challenge) => challenge,
+        None => return Ok(()),
+    };
+    let message_id = match challenge.message_id {
+        Some(message_id) => message_id,
+        None => return Ok(()),
+    };
+    let remaining = Duration::from_secs(challenge.expires_at.saturating_sub(unix_time()));
+    if remaining < interval {
+        // Question will be deleted soon
+        return Ok(());
+    }
+    if !context.api.has_spare_capacity(chat_id) {
+        // Edits of many questions in one chat would exhaust the limit for new questions
+        log::info!("Question #{} update is postponed, chat limit is reached", message_id);
+        context
+            .scheduler
+            .schedule(interval, Job::UpdateQuestion { chat_id, user_id });
+        return Ok(());
+    }
+    let user = User::from(challenge.user.clone());
+    let vars = QuestionVars {
+        user: &user,
+        batch: &[],
+        chat: ChatInfo {
+            id: chat_id,
+            title: challenge.chat_title.as_deref(),
+            username: challenge.chat_username.as_deref(),
+        },
+        joined_at: challenge.joined_at,
+        remaining,
+    };
+    let text = config.render_question(challenge.level, &vars)?.replace("\\n", "\n");
+    let keyboard = question::build_keyboard(config, challenge.level, chat_id, Some(user_id))?;
+    let delay = match question::edit(context, config, &challenge, message_id, text, keyboard).await {
+        Ok(()) => {
+            log::info!("Question #{} updated", message_id);
+            interval
+        }
+        Err(ExecuteError::Response(err)) if err.can_retry() => {
+            let retry_after = err.retry_after().unwrap_or(0) as u64;
+            log::info!("Question update is rate limited, retry after {} second(s)", retry_after);
+            interval.max(Duration::from_secs(retry_after))
+        }
+        Err(err) => {
+            log::info!("Stop updating question #{}: {}", message_id, err);
+            return Ok(());
+        }
+    };
+    context
+        .scheduler
+        .schedule(delay, Job::UpdateQuestion { chat_id, user_id });
+    Ok(())
+}
+
+/// Deletes a question which was not answered in time and applies timeout action
+async fn expire_challenge(context: &Context, chat_id: Integer, user_id: Integer) -> Result<(), HandlerError> {
+    if !context
+        .challenges
+        .transition(chat_id, user_id, ChallengeState::Asked, ChallengeState::Expired)
+    {
+        // User responded to question
+        return Ok(());
+    }
+    let challenge = match context
+        .store
+        .load_challenge(chat_id, user_id)
+        .await
+        .map_err(HandlerError::LoadChallenge)?
+    {
+        Some(challenge) => challenge,
+        None => {
+            log::warn!("Pending challenge not found (chat_id={}, user_id={})", chat_id, user_id);
+            return Ok(());
+        }
+    };
+    context
+        .store
+        .remove_challenge(chat_id, user_id)
+        .await
+        .map_err(HandlerError::RemoveChallenge)?;
+    context.scheduler.cancel(JobKey::update_question(chat_id, user_id));
+    question::delete(context, &challenge).await;
+    let config = match context.chats.get(&chat_id) {
+        Some(config) => config,
+        None => {
+            log::info!("Config not found for chat '{}'", chat_id);
+            return Ok(());
+        }
+    };
+    match config.action_timeout() {
+        Action::Kick => ban::ban(context, config, chat_id, user_id, "no answer", true).await?,
+        Action::Restrict => {
+            ban::distrust(context, config, user_id).await?;
+            if let Some(restrict_for) = config.restrict_for() {
+                context
+                    .scheduler
+                    .schedule(restrict_for, Job::LiftMute { chat_id, user_id });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Restores permissions which user had before joining
+async fn lift_mute(context: &Context, chat_id: Integer, user_id: Integer) -> Result<(), HandlerError> {
+    restriction::restore(context, chat_id, user_id).await?;
+    log::info!("Restriction lifted (chat_id={}, user_id={})", chat_id, user_id);
+    Ok(())
+}
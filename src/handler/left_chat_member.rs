@@ -0,0 +1,72 @@
+use crate::{
+    challenge::ChallengeState,
+    context::Context,
+    handler::{ban, error::HandlerError, question, unix_time},
+    scheduler::{Job, JobKey},
+};
+use carapax::types::{Message, User};
+
+/// Closes a challenge of a user who left the chat before answering
+///
+/// A user removed by someone else is not trusted in the chat network anymore.
+pub(super) async fn handle(context: &Context, message: &Message, user: &User) -> Result<(), HandlerError> {
+    let chat_id = message.get_chat_id();
+    let user_id = user.id;
+    let config = match context.chats.get(&chat_id) {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    if message.get_user().map_or(false, |x| x.id != user_id) {
+        ban::distrust(context, config, user_id).await?;
+    }
+    if context
+        .challenges
+        .transition(chat_id, user_id, ChallengeState::Scheduled, ChallengeState::Left)
+    {
+        context.scheduler.cancel(JobKey::send_question(chat_id, user_id));
+    } else if context
+        .challenges
+        .transition(chat_id, user_id, ChallengeState::Asked, ChallengeState::Left)
+    {
+        context.scheduler.cancel(JobKey::expire_challenge(chat_id, user_id));
+        context.scheduler.cancel(JobKey::update_question(chat_id, user_id));
+        let challenge = context
+            .store
+            .load_challenge(chat_id, user_id)
+            .await
+            .map_err(HandlerError::LoadChallenge)?;
+        if let Some(challenge) = challenge {
+            context
+                .store
+                .remove_challenge(chat_id, user_id)
+                .await
+                .map_err(HandlerError::RemoveChallenge)?;
+            question::delete(context, &challenge).await;
+        }
+    } else {
+        return Ok(());
+    }
+    log::info!("User left before answering (chat_id={}, user_id={})", chat_id, user_id);
+    if let Some(rejoin) = config.rejoin() {
+        let now = unix_time();
+        let mut leaves = context
+            .store
+            .load_leaves(chat_id, user_id)
+            .await
+            .map_err(HandlerError::LoadLeaves)?;
+        let since = now.saturating_sub(rejoin.period().as_secs());
+        leaves.retain(|time| *time > since);
+        leaves.push(now);
+        context
+            .store
+            .save_leaves(chat_id, user_id, &leaves)
+            .await
+            .map_err(HandlerError::SaveLeaves)?;
+        // History is not needed when user does not leave again within the period,
+        // a next leave replaces the job
+        context
+            .scheduler
+            .schedule(rejoin.period(), Job::ForgetLeaves { chat_id, user_id });
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use crate::{
+    api::{Priority, Target},
+    config::{ChatConfig, ChatInfo, LockdownVars, RaidConfig},
+    context::Context,
+    handler::{alert, error::HandlerError},
+    scheduler::Job,
+};
+use carapax::{
+    methods::{EditMessageText, SendMessage},
+    types::Integer,
+};
+
+/// Starts a lockdown if it is not started yet
+///
+/// Sends a message to the chat and alerts admins.
+/// A message is sent once and updated with a summary when lockdown ends.
+pub(super) async fn start(context: &Context, config: &ChatConfig, raid: &RaidConfig, chat: ChatInfo<'_>, joins: usize) {
+    let chat_id = chat.id;
+    if !context.lockdowns.start(chat_id, chat.title, chat.username) {
+        return;
+    }
+    log::info!("Lockdown started (chat_id={})", chat_id);
+    match config.render_lockdown_started(&LockdownVars { chat, joins }) {
+        Ok(Some(text)) => {
+            let mut method = SendMessage::new(chat_id, text);
+            if let Some(parse_mode) = config.parse_mode() {
+                method = method.parse_mode(parse_mode);
+            }
+            match context
+                .api
+                .execute_with(Priority::Normal, Target::message(chat_id), method)
+                .await
+            {
+                Ok(message) => context.lockdowns.set_message(chat_id, message.id),
+                Err(err) => log::warn!("Failed to send lockdown message (chat_id={}): {}", chat_id, err),
+            }
+        }
+        Ok(None) => {}
+        Err(err) => log::warn!("Failed to render lockdown message (chat_id={}): {}", chat_id, err),
+    }
+    let text = format!(
+        "Raid detected: {} user(s) joined within {} second(s), lockdown started (chat_id={})",
+        joins,
+        raid.period().as_secs(),
+        chat_id
+    );
+    alert::send(context, config, chat_id, text).await;
+}
+
+/// Ends a lockdown after quiet period
+pub(super) async fn end(context: &Context, chat_id: Integer) -> Result<(), HandlerError> {
+    let lockdown = match context.lockdowns.end(chat_id) {
+        Some(lockdown) => lockdown,
+        None => return Ok(()),
+    };
+    log::info!(
+        "Lockdown ended (chat_id={}), {} user(s) joined",
+        chat_id,
+        lockdown.joins
+    );
+    let config = match context.chats.get(&chat_id) {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let vars = LockdownVars {
+        chat: ChatInfo {
+            id: chat_id,
+            title: lockdown.chat_title.as_deref(),
+            username: lockdown.chat_username.as_deref(),
+        },
+        joins: lockdown.joins,
+    };
+    if let Some(message_id) = lockdown.message_id {
+        match config.render_lockdown_ended(&vars) {
+            Ok(Some(text)) => {
+                let mut method = EditMessageText::new(chat_id, message_id, text);
+                if let Some(parse_mode) = config.parse_mode() {
+                    method = method.parse_mode(parse_mode);
+                }
+                if let Err(err) = context
+                    .api
+                    .execute_with(Priority::Normal, Target::message(chat_id), method)
+                    .await
+                {
+                    log::warn!("Failed to update lockdown message #{}: {}", message_id, err);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!("Failed to render lockdown summary (chat_id={}): {}", chat_id, err),
+        }
+    }
+    let text = format!(
+        "Lockdown is over, {} user(s) joined during lockdown (chat_id={})",
+        lockdown.joins, chat_id
+    );
+    alert::send(context, config, chat_id, text).await;
+    Ok(())
+}
+
+/// Counts joins during an active lockdown and postpones its end
+///
+/// Returns false if chat is not in lockdown.
+pub(super) fn add_joins(context: &Context, config: &RaidConfig, chat_id: Integer, count: usize) -> bool {
+    let lockdown = match config.lockdown() {
+        Some(lockdown) => lockdown,
+        None => return false,
+    };
+    if !context.lockdowns.add_joins(chat_id, count) {
+        return false;
+    }
+    context
+        .scheduler
+        .schedule(lockdown.quiet_period(), Job::EndLockdown { chat_id });
+    true
+}
@@ -1,9 +1,11 @@
 use crate::{
+    api::{Priority, Target},
     context::Context,
-    handler::{error::HandlerError, new_chat_member},
+    handler::{error::HandlerError, left_chat_member, new_chat_member},
 };
 use carapax::{
     handler,
+    methods::DeleteMessage,
     types::{Message, MessageData},
 };
 
@@ -14,9 +16,39 @@ pub async fn handle(context: &Context, message: Message) -> Result<(), HandlerEr
         message.get_chat_id(),
         message.get_chat_username()
     );
-    if let MessageData::NewChatMembers(ref users) = message.data {
-        let chat_id = message.get_chat_id();
-        new_chat_member::handle(context, chat_id, message.id, users).await?;
+    match message.data {
+        MessageData::NewChatMembers(ref users) => new_chat_member::handle(context, &message, users).await?,
+        MessageData::LeftChatMember(ref user) => left_chat_member::handle(context, &message, user).await?,
+        _ => delete_unverified(context, &message).await?,
     }
     Ok(())
 }
+
+/// Deletes a message of a user who did not pass a challenge yet
+///
+/// Such message could be sent before restriction is applied.
+async fn delete_unverified(context: &Context, message: &Message) -> Result<(), HandlerError> {
+    let chat_id = message.get_chat_id();
+    let user_id = match message.get_user() {
+        Some(user) => user.id,
+        None => return Ok(()),
+    };
+    if context.challenges.get(chat_id, user_id).is_none() {
+        return Ok(());
+    }
+    context
+        .api
+        .execute_with(
+            Priority::Normal,
+            Target::chat(chat_id),
+            DeleteMessage::new(chat_id, message.id),
+        )
+        .await?;
+    log::info!(
+        "Message #{} of unverified user deleted (chat_id={}, user_id={})",
+        message.id,
+        chat_id,
+        user_id
+    );
+    Ok(())
+}
@@ -1,6 +1,26 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod alert;
+mod ban;
 mod callback_query;
 mod error;
+mod jobs;
+mod left_chat_member;
+mod lockdown;
 mod message;
 mod new_chat_member;
+mod question;
+mod restriction;
+mod risk;
+
+pub use self::{
+    callback_query::handle as on_callback_query, jobs::execute as execute_job, message::handle as on_message,
+};
 
-pub use self::{callback_query::handle as on_callback_query, message::handle as on_message};
+/// Returns current unix time in seconds
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
@@ -1,27 +1,37 @@
 use crate::{
-    config::Action,
-    context::{Context, Payload},
-    handler::error::HandlerError,
-    permissions::{obtain_chat_member_permissions, PERMISSIONS_SESSION_KEY},
+    api::{Priority, Target},
+    challenge::{ChallengeState, TryAsk},
+    config::{
+        ChallengeLevel, ChatConfig, ChatInfo, LockdownConfig, NameRuleAction, Overflow, QuestionVars, RejoinConfig,
+        Verification,
+    },
+    context::{Context, MEDIA_MESSAGE_SESSION_KEY},
+    handler::{alert, ban, error::HandlerError, lockdown, question, restriction, risk, unix_time},
+    permissions::is_admin,
+    scheduler::{Job, JobKey, QuestionRequest},
+    store::PendingChallenge,
 };
 use carapax::{
-    methods::{DeleteMessage, GetChatMember, KickChatMember, RestrictChatMember, SendMessage},
-    session::SessionId,
-    types::{InlineKeyboardButton, Integer, ParseMode, User},
-    Api,
+    methods::{GetChatMember, RestrictChatMember, SendMessage},
+    session::{backend::fs::FilesystemBackend, Session, SessionId},
+    types::{Integer, Message, User},
 };
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use tokio::{task, time::delay_for};
+/// A queued user is checked again after this interval
+const QUEUE_RETRY_INTERVAL: Duration = Duration::from_secs(10);
 
-const PARSE_MODE: ParseMode = ParseMode::Html;
+/// A user postponed because of overflow more times is kicked
+const MAX_OVERFLOWS: u32 = 30;
 
-pub(super) async fn handle(
-    context: &Context,
-    chat_id: Integer,
-    message_id: Integer,
-    users: &[User],
-) -> Result<(), HandlerError> {
+/// A user kicked because of overflow can join again after this period
+const OVERFLOW_KICK_PERIOD: Duration = Duration::from_secs(60);
+
+/// A period to collect users for a batch on overflow or during lockdown when `batch_window` is not set
+const DEFAULT_BATCH_WINDOW: Duration = Duration::from_secs(5);
+
+pub(super) async fn handle(context: &Context, message: &Message, users: &[User]) -> Result<(), HandlerError> {
+    let chat_id = message.get_chat_id();
     let config = match context.chats.get(&chat_id) {
         Some(config) => config,
         None => {
@@ -29,100 +39,738 @@ pub(super) async fn handle(
             return Ok(());
         }
     };
-    if let Some(timeout) = config.ask_timeout() {
-        log::info!("Waiting for {} second(s) timeout before question", timeout.as_secs());
-        delay_for(timeout).await;
-    }
+    let chat = ChatInfo::from_message(message);
+    // A user who joined on their own is the sender of the message
+    let adder_id = message.get_user().map(|x| x.id);
+    // Federated bans are lifted when admins add a user
+    let check_adder = config.bypass().added_by_admin()
+        || config.bots().map_or(false, |x| x.added_by_admin())
+        || config.network().map_or(false, |x| x.federated_bans());
+    let added_by_admin = match adder_id {
+        Some(adder_id) if check_adder && users.iter().any(|x| x.id != adder_id) => {
+            let adder = context
+                .api
+                .execute_with(
+                    Priority::Normal,
+                    Target::member(chat_id, adder_id),
+                    GetChatMember::new(chat_id, adder_id),
+                )
+                .await?;
+            is_admin(&adder)
+        }
+        _ => false,
+    };
+    let (is_raid, lockdown) = match config.raid() {
+        Some(raid) => {
+            let joins = context.joins.record(chat_id, users.len(), raid.period());
+            let is_raid = joins > raid.joins();
+            if is_raid {
+                log::info!("{} user(s) joined chat {} recently, it is a raid", joins, chat_id);
+                if raid.lockdown().is_some() {
+                    lockdown::start(context, config, raid, chat, joins).await;
+                }
+            }
+            let lockdown = if lockdown::add_joins(context, raid, chat_id, users.len()) {
+                raid.lockdown()
+            } else {
+                None
+            };
+            (is_raid, lockdown)
+        }
+        None => (false, None),
+    };
+    let joined = Joined {
+        chat_id,
+        chat_title: chat.title.map(String::from),
+        chat_username: chat.username.map(String::from),
+        message_id: message.id,
+        joined_at: message.date,
+        adder_id,
+        added_by_admin,
+        is_raid,
+        is_lockdown: lockdown.is_some(),
+    };
+    // Restriction may be retried for a long time, so updates are not held by it
+    tokio::spawn(verify(context.clone(), Arc::clone(config), joined, users.to_vec()));
+    Ok(())
+}
+
+/// A message about new chat members
+struct Joined {
+    chat_id: Integer,
+    chat_title: Option<String>,
+    chat_username: Option<String>,
+    message_id: Integer,
+    joined_at: Integer,
+    adder_id: Option<Integer>,
+    added_by_admin: bool,
+    is_raid: bool,
+    is_lockdown: bool,
+}
+
+/// Verifies new chat members and asks those who must answer a question
+///
+/// Errors are logged, so a failure for one user does not affect others.
+async fn verify(context: Context, config: Arc<ChatConfig>, joined: Joined, users: Vec<User>) {
+    let (context, config, chat_id) = (&context, config.as_ref(), joined.chat_id);
+    let lockdown = config.raid().and_then(|x| x.lockdown()).filter(|_| joined.is_lockdown);
+    let mut requests = Vec::with_capacity(users.len());
     for user in users {
-        let user_id = user.id;
-        let chat_member = context.api.execute(GetChatMember::new(chat_id, user_id)).await?;
-        let permissions = obtain_chat_member_permissions(chat_member);
-        let mut session = context
-            .session_manager
-            .get_session(SessionId::new(chat_id, user_id))
-            .expect("Failed to get session"); // Should never panic as we provided SessionId
-        session
-            .set(PERMISSIONS_SESSION_KEY, &permissions)
-            .await
-            .map_err(HandlerError::SavePermissions)?;
-        context
-            .api
-            .execute(RestrictChatMember::new(chat_id, user_id).restrict_all())
-            .await?;
-        let question = config.render_question(&user, PARSE_MODE)?.replace("\\n", "\n");
-        let mut buttons = Vec::new();
-        for button in config.buttons() {
-            buttons.push(InlineKeyboardButton::with_callback_data_struct(
-                button.label(),
-                &Payload {
+        let is_added_by_admin = joined.added_by_admin && joined.adder_id != Some(user.id);
+        let level = match verify_user(context, config, &joined, &user, is_added_by_admin, lockdown).await {
+            Ok(Some(level)) => level,
+            Ok(None) => continue,
+            Err(err) => {
+                log::error!("Failed to verify user {} (chat_id={}): {}", user.id, chat_id, err);
+                continue;
+            }
+        };
+        context.challenges.start(chat_id, user.id, ChallengeState::Scheduled);
+        // Only the question waits for ask timeout, user must not be able to write before it
+        match restrict(context, config, chat_id, user.id).await {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(err) => {
+                // User can write until answered, but still must answer to stay in the chat
+                log::error!(
+                    "Failed to restrict user {} (chat_id={}), asking anyway: {}",
+                    user.id,
                     chat_id,
-                    user_id,
-                    is_right: button.is_right(),
-                },
-            )?)
-        }
-        let message = context
-            .api
-            .execute(
-                SendMessage::new(chat_id, question)
-                    .reply_markup(vec![buttons])
-                    .reply_to_message_id(message_id)
-                    .parse_mode(PARSE_MODE),
-            )
-            .await?;
-        let timeout_handler = TimeoutHandler {
-            api: context.api.clone(),
-            timeout: config.response_timeout(),
+                    err
+                );
+                let text = format!(
+                    "User {} ({}) could not be restricted on join (chat_id={})",
+                    user.get_full_name(),
+                    user.id,
+                    chat_id
+                );
+                alert::send(context, config, chat_id, text).await;
+            }
+        }
+        let request = QuestionRequest {
             chat_id,
-            message_id: message.id,
-            user_id,
-            action: config.action_timeout(),
+            chat_title: joined.chat_title.clone(),
+            chat_username: joined.chat_username.clone(),
+            user: (&user).into(),
+            message_id: joined.message_id,
+            joined_at: joined.joined_at,
+            level,
+            overflows: 0,
         };
-        task::spawn(timeout_handler.run());
+        requests.push(request);
+    }
+    // Questions are sent immediately during lockdown, a single question is sent for users joined within a window
+    let ask_timeout = config.ask_timeout().filter(|_| lockdown.is_none());
+    let batch_window = match lockdown {
+        Some(_) => Some(config.batch_window().unwrap_or(DEFAULT_BATCH_WINDOW)),
+        None => config.batch_window(),
+    };
+    match batch_window {
+        Some(_) if requests.is_empty() => {}
+        Some(window) => {
+            let delay = ask_timeout.unwrap_or_default().max(window);
+            if delay.as_secs() == 0 {
+                ask_batch(context, config, requests).await;
+            } else {
+                let (batch_id, delay, requests) = context.batches.push(chat_id, requests, delay);
+                log::info!(
+                    "{} user(s) will be asked by a single question after {} second(s)",
+                    requests.len(),
+                    delay.as_secs()
+                );
+                context.scheduler.schedule(
+                    delay,
+                    Job::SendBatch {
+                        chat_id,
+                        batch_id,
+                        requests,
+                    },
+                );
+            }
+        }
+        None => {
+            for request in requests {
+                match ask_timeout {
+                    Some(timeout) => {
+                        log::info!(
+                            "Question for user {} will be sent after {} second(s)",
+                            request.user.id,
+                            timeout.as_secs()
+                        );
+                        context.scheduler.schedule(timeout, Job::SendQuestion(request));
+                    }
+                    None => {
+                        let user_id = request.user.id;
+                        if let Err(err) = ask(context, config, request).await {
+                            log::error!("Failed to ask user {} (chat_id={}): {}", user_id, chat_id, err);
+                        }
+                    }
+                }
+            }
+        }
     }
-    Ok(())
 }
 
-struct TimeoutHandler {
-    api: Api,
-    timeout: Duration,
-    chat_id: Integer,
-    message_id: Integer,
-    user_id: Integer,
-    action: Action,
+/// Kicks or bans a new chat member if required
+///
+/// Returns a challenge level when user must answer a question.
+async fn verify_user(
+    context: &Context,
+    config: &ChatConfig,
+    joined: &Joined,
+    user: &User,
+    is_added_by_admin: bool,
+    lockdown: Option<&LockdownConfig>,
+) -> Result<Option<ChallengeLevel>, HandlerError> {
+    let chat_id = joined.chat_id;
+    let verification = get_verification(
+        context,
+        config,
+        chat_id,
+        user,
+        is_added_by_admin,
+        joined.is_raid,
+        lockdown,
+    )
+    .await?;
+    match verification {
+        Verification::Skip => Ok(None),
+        Verification::Kick { reason, period } => {
+            ban::kick(context, config, chat_id, user.id, period, &reason).await?;
+            Ok(None)
+        }
+        Verification::Ban { reason, federate } => {
+            ban::ban(context, config, chat_id, user.id, &reason, federate).await?;
+            Ok(None)
+        }
+        Verification::Challenge(level) => Ok(Some(level)),
+    }
 }
 
-impl TimeoutHandler {
-    async fn run(self) {
+/// Decides how to verify a new chat member
+async fn get_verification(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    user: &User,
+    is_added_by_admin: bool,
+    is_raid: bool,
+    lockdown: Option<&LockdownConfig>,
+) -> Result<Verification, HandlerError> {
+    // Score is logged for every join, so weights can be tuned
+    let risk = match config.risk() {
+        Some(risk) => {
+            let is_flagged = config
+                .name_rules()
+                .iter()
+                .any(|x| x.action() == NameRuleAction::Flag && x.is_match(user));
+            let score = risk::score(context, risk, user, is_flagged, is_raid).await;
+            log::info!("Risk score of user {} is {} ({})", user.id, score.total(), score);
+            Some((risk, score))
+        }
+        None => None,
+    };
+    if config.bypass().has_user(user.id) {
+        log::info!("User {} is in allowlist, skipping verification", user.id);
+        return Ok(Verification::Skip);
+    }
+    if let Some(reason) = context.blocklist.as_ref().and_then(|x| x.find(user.id)) {
+        log::info!("User {} is in blocklist: {}", user.id, reason);
+        // Blocklist applies to all chats already, a federated ban would outlive an entry removed from the file
+        return Ok(Verification::Ban {
+            reason: format!("blocklist: {}", reason),
+            federate: false,
+        });
+    }
+    if let Some(network) = config.network().filter(|x| x.federated_bans()) {
+        let federated_ban = context
+            .store
+            .load_ban(network.name(), user.id)
+            .await
+            .map_err(HandlerError::LoadBan)?;
+        match federated_ban {
+            Some(ref federated_ban)
+                if federated_ban.is_expired(network.ban_for(), unix_time()) || is_added_by_admin =>
+            {
+                // Admins unban a user by adding them back
+                context
+                    .store
+                    .remove_ban(network.name(), user.id)
+                    .await
+                    .map_err(HandlerError::RemoveBan)?;
+                log::info!(
+                    "Ban of user {} is lifted in network '{}' (chat_id={})",
+                    user.id,
+                    network.name(),
+                    chat_id
+                );
+            }
+            Some(federated_ban) => {
+                log::info!(
+                    "User {} is banned in network '{}' (chat_id={}): {}",
+                    user.id,
+                    network.name(),
+                    federated_ban.chat_id,
+                    federated_ban.reason
+                );
+                return Ok(Verification::Ban {
+                    reason: format!("banned in network '{}': {}", network.name(), federated_ban.reason),
+                    federate: true,
+                });
+            }
+            None => {}
+        }
+    }
+    if user.is_bot {
+        if let Some(bots) = config.bots() {
+            let is_allowed = (bots.added_by_admin() && is_added_by_admin)
+                || user.username.as_deref().map_or(false, |x| bots.has_username(x));
+            return Ok(if is_allowed {
+                log::info!("Bot {} is allowed, skipping verification", user.id);
+                Verification::Skip
+            } else {
+                log::info!("Bot {} is not allowed", user.id);
+                // Allowed bots are configured per chat, so the bot may be allowed in other chats
+                Verification::Ban {
+                    reason: String::from("bot is not allowed"),
+                    federate: false,
+                }
+            });
+        }
+    }
+    if is_added_by_admin && config.bypass().added_by_admin() {
+        log::info!("User {} is added by an admin, skipping verification", user.id);
+        return Ok(Verification::Skip);
+    }
+    if let Some(lockdown) = lockdown {
+        log::info!("User {} joined during lockdown: {:?}", user.id, lockdown.verification());
+        return Ok(lockdown.verification());
+    }
+    if let Some(rejoin) = config.rejoin() {
+        if is_rejoin_limit_exceeded(context, rejoin, chat_id, user.id).await? {
+            log::info!("User {} rejoined too often", user.id);
+            // Rejoin limit is a policy of a chat, other chats of the network may not limit rejoins
+            return Ok(Verification::Ban {
+                reason: String::from("rejoined too often"),
+                federate: false,
+            });
+        }
+    }
+    let mut action = None;
+    for rule in config.name_rules().iter().filter(|x| x.is_match(user)) {
         log::info!(
-            "Waiting for {} second(s) timeout before deleting question",
-            self.timeout.as_secs()
+            "User {} matches name rule '{}': {:?}",
+            user.id,
+            rule.pattern(),
+            rule.action()
         );
-        delay_for(self.timeout).await;
-        match self
-            .api
-            .execute(DeleteMessage::new(self.chat_id, self.message_id))
+        if rule.action() == NameRuleAction::Flag {
+            let text = format!(
+                "User {} ({}) matches name rule '{}' (chat_id={})",
+                user.get_full_name(),
+                user.id,
+                rule.pattern(),
+                chat_id
+            );
+            alert::send(context, config, chat_id, text).await;
+        }
+        action = action.max(Some(rule.action()));
+    }
+    match action {
+        Some(NameRuleAction::Ban) => {
+            let rules = config
+                .name_rules()
+                .iter()
+                .filter(|x| x.action() == NameRuleAction::Ban && x.is_match(user))
+                .collect::<Vec<_>>();
+            return Ok(Verification::Ban {
+                reason: format!(
+                    "name matches rule '{}'",
+                    rules.iter().map(|x| x.pattern()).collect::<Vec<_>>().join("', '")
+                ),
+                federate: rules.iter().any(|x| x.federate()),
+            });
+        }
+        Some(NameRuleAction::Hard) => return Ok(Verification::Challenge(ChallengeLevel::Hard)),
+        Some(NameRuleAction::Flag) | None => {}
+    }
+    if let Some(network) = config.network() {
+        let trusted_at = context
+            .store
+            .load_trusted(network.name(), user.id)
             .await
-        {
-            Ok(_) => {
-                // User not respond to question
-                log::info!("Question #{} successfully deleted", self.message_id);
-                if let Action::Kick = self.action {
-                    match self.api.execute(KickChatMember::new(self.chat_id, self.user_id)).await {
-                        Ok(_) => log::info!("Chat member kicked"),
-                        Err(err) => log::warn!(
-                            "Failed to kick chat member (chat_id={}, user_id={}): {}",
-                            self.chat_id,
-                            self.user_id,
-                            err
-                        ),
-                    }
+            .map_err(HandlerError::LoadTrusted)?;
+        let is_trusted = match (trusted_at, network.trusted_for()) {
+            (Some(trusted_at), Some(trusted_for)) => trusted_at + trusted_for.as_secs() > unix_time(),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if is_trusted {
+            log::info!(
+                "User {} is trusted in network '{}': {:?}",
+                user.id,
+                network.name(),
+                network.trusted()
+            );
+            return Ok(network.trusted());
+        }
+    }
+    if let Some((risk, score)) = risk {
+        let verification = risk.verification(score.total());
+        log::info!("User {} is verified by risk score: {:?}", user.id, verification);
+        return Ok(verification);
+    }
+    Ok(Verification::Challenge(ChallengeLevel::Normal))
+}
+
+/// Restricts a new chat member until a question is answered
+///
+/// Returns false when user is an admin and must not be asked.
+async fn restrict(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    user_id: Integer,
+) -> Result<bool, HandlerError> {
+    let chat_member = context
+        .api
+        .execute_with(
+            Priority::Normal,
+            Target::member(chat_id, user_id),
+            GetChatMember::new(chat_id, user_id),
+        )
+        .await?;
+    if config.bypass().admins() && is_admin(&chat_member) {
+        log::info!("User {} is an admin, skipping verification", user_id);
+        context.challenges.remove(chat_id, user_id, ChallengeState::Scheduled);
+        return Ok(false);
+    }
+    restriction::remember(context, chat_id, user_id, &chat_member).await?;
+    context
+        .api
+        .execute_with(
+            Priority::High,
+            Target::member(chat_id, user_id),
+            RestrictChatMember::new(chat_id, user_id).restrict_all(),
+        )
+        .await?;
+    Ok(true)
+}
+
+/// Sends a question to a new chat member restricted on join
+pub(super) async fn ask(context: &Context, config: &ChatConfig, request: QuestionRequest) -> Result<(), HandlerError> {
+    match prepare(context, config, request.chat_id, request.user.id) {
+        TryAsk::Asked => send_question(context, config, vec![request]).await,
+        TryAsk::NotScheduled => Ok(()),
+        TryAsk::Full => overflow(context, config, request).await,
+    }
+}
+
+/// Sends a single question for each challenge level to new chat members restricted on join
+///
+/// Errors are logged, so a failure for one user does not leave others without a question.
+pub(super) async fn ask_batch(context: &Context, config: &ChatConfig, requests: Vec<QuestionRequest>) {
+    let mut asked = Vec::with_capacity(requests.len());
+    for request in requests {
+        match prepare(context, config, request.chat_id, request.user.id) {
+            TryAsk::Asked => asked.push(request),
+            TryAsk::NotScheduled => {}
+            TryAsk::Full => {
+                if let Err(err) = overflow(context, config, request).await {
+                    log::error!("Failed to apply overflow policy: {}", err);
                 }
             }
-            Err(err) => {
-                // Possibly user respond to question
-                log::info!("Failed to delete question: {}", err)
+        }
+    }
+    for level in &[ChallengeLevel::Easy, ChallengeLevel::Normal, ChallengeLevel::Hard] {
+        let (batch, rest): (Vec<QuestionRequest>, Vec<QuestionRequest>) =
+            asked.into_iter().partition(|x| x.level == *level);
+        asked = rest;
+        if batch.is_empty() {
+            continue;
+        }
+        if let Err(err) = send_question(context, config, batch).await {
+            log::error!("Failed to send a batch question: {}", err);
+        }
+    }
+}
+
+/// Marks a challenge as asked before sending a question, unless `max_pending` is reached
+fn prepare(context: &Context, config: &ChatConfig, chat_id: Integer, user_id: Integer) -> TryAsk {
+    let result = context.challenges.try_ask(chat_id, user_id, config.max_pending());
+    if result == TryAsk::NotScheduled {
+        log::info!(
+            "Challenge is not scheduled anymore (chat_id={}, user_id={})",
+            chat_id,
+            user_id
+        );
+    }
+    result
+}
+
+/// Applies overflow policy to a user who can not be asked now
+///
+/// A user who could not be asked after `MAX_OVERFLOWS` attempts is kicked, so users are not postponed forever.
+async fn overflow(context: &Context, config: &ChatConfig, mut request: QuestionRequest) -> Result<(), HandlerError> {
+    let (chat_id, user_id) = (request.chat_id, request.user.id);
+    request.overflows += 1;
+    let policy = if request.overflows > MAX_OVERFLOWS {
+        log::info!(
+            "User {} could not be asked {} times (chat_id={})",
+            user_id,
+            MAX_OVERFLOWS,
+            chat_id
+        );
+        Overflow::Kick
+    } else {
+        config.overflow()
+    };
+    match policy {
+        Overflow::Kick => {
+            if !context
+                .challenges
+                .transition(chat_id, user_id, ChallengeState::Scheduled, ChallengeState::Kicked)
+            {
+                return Ok(());
+            }
+            ban::kick(
+                context,
+                config,
+                chat_id,
+                user_id,
+                OVERFLOW_KICK_PERIOD,
+                "too many pending questions",
+            )
+            .await?;
+            let mut text = format!(
+                "The chat is busy, please join again in {} seconds.",
+                OVERFLOW_KICK_PERIOD.as_secs()
+            );
+            if let Some(username) = request.chat_username {
+                text = format!("{} https://t.me/{}", text, username);
+            }
+            if let Err(err) = context
+                .api
+                .execute_with(Priority::Low, Target::user(user_id), SendMessage::new(user_id, text))
+                .await
+            {
+                // Bot can not write to users who did not start it
+                log::info!("Failed to invite user {} to join again: {}", user_id, err);
+            }
+        }
+        Overflow::Queue => {
+            log::info!(
+                "Too many pending questions, user {} is queued (chat_id={})",
+                user_id,
+                chat_id
+            );
+            context
+                .scheduler
+                .schedule(QUEUE_RETRY_INTERVAL, Job::SendQuestion(request));
+        }
+        Overflow::Batch => {
+            let window = config
+                .batch_window()
+                .filter(|x| x.as_secs() > 0)
+                .unwrap_or(DEFAULT_BATCH_WINDOW);
+            let (batch_id, delay, requests) = context.batches.push(chat_id, vec![request], window);
+            log::info!(
+                "Too many pending questions, user {} is added to a batch (chat_id={})",
+                user_id,
+                chat_id
+            );
+            context.scheduler.schedule(
+                delay,
+                Job::SendBatch {
+                    chat_id,
+                    batch_id,
+                    requests,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Sends a question to users of the same chat with the same challenge level
+///
+/// Keyboard of a question for several users can be used by any of them.
+/// Remaining time is not updated in such question.
+///
+/// Challenges are cancelled when question could not be sent.
+async fn send_question(
+    context: &Context,
+    config: &ChatConfig,
+    requests: Vec<QuestionRequest>,
+) -> Result<(), HandlerError> {
+    let chat_id = requests[0].chat_id;
+    let user_ids: Vec<Integer> = requests.iter().map(|x| x.user.id).collect();
+    let result = try_send_question(context, config, requests).await;
+    if result.is_err() {
+        for user_id in user_ids {
+            cancel(context, chat_id, user_id).await;
+        }
+    }
+    result
+}
+
+/// Lifts restriction of a user who could not be asked
+///
+/// Errors are logged, user must not stay restricted without a question.
+async fn cancel(context: &Context, chat_id: Integer, user_id: Integer) {
+    if !context
+        .challenges
+        .transition(chat_id, user_id, ChallengeState::Asked, ChallengeState::Passed)
+    {
+        return;
+    }
+    context.scheduler.cancel(JobKey::expire_challenge(chat_id, user_id));
+    if let Err(err) = context.store.remove_challenge(chat_id, user_id).await {
+        log::error!(
+            "Failed to remove a challenge (chat_id={}, user_id={}): {}",
+            chat_id,
+            user_id,
+            err
+        );
+    }
+    match restriction::restore(context, chat_id, user_id).await {
+        Ok(()) => log::warn!(
+            "Question was not sent, restriction lifted (chat_id={}, user_id={})",
+            chat_id,
+            user_id
+        ),
+        Err(err) => log::error!(
+            "Failed to lift restriction (chat_id={}, user_id={}): {}",
+            chat_id,
+            user_id,
+            err
+        ),
+    }
+}
+
+async fn try_send_question(
+    context: &Context,
+    config: &ChatConfig,
+    requests: Vec<QuestionRequest>,
+) -> Result<(), HandlerError> {
+    let first = &requests[0];
+    let (chat_id, level) = (first.chat_id, first.level);
+    let is_batch = requests.len() > 1;
+    let users: Vec<User> = requests.iter().map(|x| User::from(x.user.clone())).collect();
+    let vars = QuestionVars {
+        user: &users[0],
+        batch: &users[1..],
+        chat: ChatInfo {
+            id: chat_id,
+            title: first.chat_title.as_deref(),
+            username: first.chat_username.as_deref(),
+        },
+        joined_at: first.joined_at,
+        remaining: config.response_timeout(level),
+    };
+    let text = config.render_question(level, &vars)?.replace("\\n", "\n");
+    let keyboard_user_id = if is_batch { None } else { Some(first.user.id) };
+    let keyboard = question::build_keyboard(config, level, chat_id, keyboard_user_id)?;
+    let reply_to = first.message_id;
+    let user_ids: Vec<Integer> = requests.iter().map(|x| x.user.id).collect();
+    let expires_at = unix_time() + config.response_timeout(level).as_secs();
+    let mut challenges: Vec<PendingChallenge> = requests
+        .into_iter()
+        .map(|request| {
+            let user_id = request.user.id;
+            PendingChallenge {
+                chat_id,
+                chat_title: request.chat_title,
+                chat_username: request.chat_username,
+                user: request.user,
+                joined_at: request.joined_at,
+                level,
+                message_id: None,
+                media_message_id: None,
+                is_caption: false,
+                expires_at,
+                batch: user_ids.iter().copied().filter(|x| *x != user_id).collect(),
             }
+        })
+        .collect();
+    // Challenge is saved with expiration before sending,
+    // so user is not left restricted when process stops in the middle
+    for challenge in &challenges {
+        let user_id = challenge.user.id;
+        context.scheduler.schedule(
+            config.response_timeout(level),
+            Job::ExpireChallenge { chat_id, user_id },
+        );
+        if let Err(err) = context.store.save_challenge(challenge).await {
+            log::error!(
+                "Failed to save a challenge (chat_id={}, user_id={}): {}",
+                chat_id,
+                user_id,
+                err
+            );
         }
     }
+    let sent = question::send(context, config, chat_id, reply_to, text, keyboard).await?;
+    // Question is sent already, so a failure for one user must not affect others
+    for challenge in &mut challenges {
+        let user_id = challenge.user.id;
+        challenge.message_id = Some(sent.message_id);
+        challenge.media_message_id = sent.media_message_id;
+        challenge.is_caption = sent.is_caption;
+        if let Err(err) = save_sent_challenge(context, challenge).await {
+            log::error!(
+                "Failed to save a sent question (chat_id={}, user_id={}): {}",
+                chat_id,
+                user_id,
+                err
+            );
+        }
+        if let Some(interval) = config.countdown_interval().filter(|_| !is_batch) {
+            context
+                .scheduler
+                .schedule(interval, Job::UpdateQuestion { chat_id, user_id });
+        }
+    }
+    Ok(())
+}
+
+async fn save_sent_challenge(context: &Context, challenge: &PendingChallenge) -> Result<(), HandlerError> {
+    let mut session = get_session(context, challenge.chat_id, challenge.user.id);
+    match challenge.media_message_id {
+        Some(media_message_id) => session.set(MEDIA_MESSAGE_SESSION_KEY, &media_message_id).await,
+        None => session.remove(MEDIA_MESSAGE_SESSION_KEY).await,
+    }
+    .map_err(HandlerError::SaveMediaMessage)?;
+    context
+        .store
+        .save_challenge(challenge)
+        .await
+        .map_err(HandlerError::SaveChallenge)
+}
+
+fn get_session(context: &Context, chat_id: Integer, user_id: Integer) -> Session<FilesystemBackend> {
+    context
+        .session_manager
+        .get_session(SessionId::new(chat_id, user_id))
+        .expect("Failed to get session") // Should never panic as we provided SessionId
+}
+
+/// Whether user left the chat before answering too many times
+async fn is_rejoin_limit_exceeded(
+    context: &Context,
+    config: &RejoinConfig,
+    chat_id: Integer,
+    user_id: Integer,
+) -> Result<bool, HandlerError> {
+    let now = unix_time();
+    let leaves = context
+        .store
+        .load_leaves(chat_id, user_id)
+        .await
+        .map_err(HandlerError::LoadLeaves)?;
+    let count = leaves
+        .into_iter()
+        .filter(|time| time + config.period().as_secs() > now)
+        .count();
+    Ok(count > config.limit())
 }
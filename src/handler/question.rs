@@ -0,0 +1,307 @@
+use crate::{
+    api::{Priority, Target},
+    challenge::ChallengeState,
+    config::{ChallengeLevel, ChatConfig, MediaConfig, MediaKind, MediaSource},
+    context::{Context, Payload},
+    handler::error::HandlerError,
+    store::{PendingChallenge, UploadedFile},
+};
+use carapax::{
+    methods::{DeleteMessage, EditMessageCaption, EditMessageText, SendAnimation, SendMessage, SendPhoto, SendSticker},
+    types::{InlineKeyboardButton, InputFile, Integer, Message, MessageData},
+    ExecuteError,
+};
+
+/// A question sent to chat
+pub(super) struct SentQuestion {
+    /// ID of a message with buttons
+    pub(super) message_id: Integer,
+    /// ID of a separate media message, if media can not have a caption
+    pub(super) media_message_id: Option<Integer>,
+    /// Whether question text is a caption of a media message
+    pub(super) is_caption: bool,
+}
+
+pub(super) async fn send(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    reply_to: Integer,
+    text: String,
+    keyboard: Vec<Vec<InlineKeyboardButton>>,
+) -> Result<SentQuestion, HandlerError> {
+    let media = match config.media() {
+        Some(media) => media,
+        None => {
+            let message = send_text(context, config, chat_id, reply_to, text, keyboard).await?;
+            return Ok(SentQuestion {
+                message_id: message.id,
+                media_message_id: None,
+                is_caption: false,
+            });
+        }
+    };
+    // Input file is consumed by a request, so each attempt gets a new one
+    let message = match media.kind() {
+        MediaKind::Animation => {
+            context
+                .api
+                .execute_built(Priority::Normal, Target::message(chat_id), || async {
+                    let file = get_input_file(context, media).await?;
+                    let mut method = SendAnimation::new(chat_id, file)
+                        .caption(text.clone())
+                        .reply_to_message_id(reply_to)
+                        .reply_markup(keyboard.clone())?;
+                    if let Some(parse_mode) = config.parse_mode() {
+                        method = method.parse_mode(parse_mode);
+                    }
+                    Ok::<_, HandlerError>(method)
+                })
+                .await?
+        }
+        MediaKind::Photo => {
+            context
+                .api
+                .execute_built(Priority::Normal, Target::message(chat_id), || async {
+                    let file = get_input_file(context, media).await?;
+                    let mut method = SendPhoto::new(chat_id, file)
+                        .caption(text.clone())
+                        .reply_to_message_id(reply_to)
+                        .reply_markup(keyboard.clone())?;
+                    if let Some(parse_mode) = config.parse_mode() {
+                        method = method.parse_mode(parse_mode);
+                    }
+                    Ok::<_, HandlerError>(method)
+                })
+                .await?
+        }
+        MediaKind::Sticker => {
+            let sticker = context
+                .api
+                .execute_built(Priority::Normal, Target::message(chat_id), || async {
+                    let file = get_input_file(context, media).await?;
+                    Ok::<_, HandlerError>(SendSticker::new(chat_id, file).reply_to_message_id(reply_to))
+                })
+                .await?;
+            cache_file_id(context, media, &sticker).await;
+            let message = match send_text(context, config, chat_id, reply_to, text, keyboard).await {
+                Ok(message) => message,
+                Err(err) => {
+                    // Sticker is not saved in a challenge yet, so nothing else would delete it
+                    match context
+                        .api
+                        .execute_with(
+                            Priority::Low,
+                            Target::chat(chat_id),
+                            DeleteMessage::new(chat_id, sticker.id),
+                        )
+                        .await
+                    {
+                        Ok(_) => log::info!("Media message #{} successfully deleted", sticker.id),
+                        Err(err) => log::warn!("Failed to delete media message: {}", err),
+                    }
+                    return Err(err);
+                }
+            };
+            return Ok(SentQuestion {
+                message_id: message.id,
+                media_message_id: Some(sticker.id),
+                is_caption: false,
+            });
+        }
+    };
+    cache_file_id(context, media, &message).await;
+    Ok(SentQuestion {
+        message_id: message.id,
+        media_message_id: None,
+        is_caption: true,
+    })
+}
+
+/// Replaces text of a sent question
+///
+/// Keyboard must be sent again, otherwise telegram removes it.
+/// Edits count against the per chat limit with low priority, so questions are sent first.
+pub(super) async fn edit(
+    context: &Context,
+    config: &ChatConfig,
+    challenge: &PendingChallenge,
+    message_id: Integer,
+    text: String,
+    keyboard: Vec<Vec<InlineKeyboardButton>>,
+) -> Result<(), ExecuteError> {
+    if challenge.is_caption {
+        let mut method = EditMessageCaption::new(challenge.chat_id, message_id)
+            .caption(text)
+            .reply_markup(keyboard);
+        if let Some(parse_mode) = config.parse_mode() {
+            method = method.parse_mode(parse_mode);
+        }
+        context
+            .api
+            .execute_with(Priority::Low, Target::message(challenge.chat_id), method)
+            .await?;
+    } else {
+        let mut method = EditMessageText::new(challenge.chat_id, message_id, text).reply_markup(keyboard);
+        if let Some(parse_mode) = config.parse_mode() {
+            method = method.parse_mode(parse_mode);
+        }
+        context
+            .api
+            .execute_with(Priority::Low, Target::message(challenge.chat_id), method)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Deletes a question and a separate media message
+///
+/// A batch question is kept until all users asked by it answered.
+pub(super) async fn delete(context: &Context, challenge: &PendingChallenge) {
+    let chat_id = challenge.chat_id;
+    let message_id = match challenge.message_id {
+        Some(message_id) => message_id,
+        None => {
+            log::info!(
+                "Question was not sent (chat_id={}, user_id={})",
+                chat_id,
+                challenge.user.id
+            );
+            return;
+        }
+    };
+    if challenge
+        .batch
+        .iter()
+        .any(|user_id| context.challenges.get(chat_id, *user_id) == Some(ChallengeState::Asked))
+    {
+        log::info!("Question #{} is kept for other users", message_id);
+        return;
+    }
+    match context
+        .api
+        .execute_with(
+            Priority::Low,
+            Target::chat(chat_id),
+            DeleteMessage::new(chat_id, message_id),
+        )
+        .await
+    {
+        Ok(_) => log::info!("Question #{} successfully deleted", message_id),
+        Err(err) => log::warn!("Failed to delete question: {}", err),
+    }
+    if let Some(media_message_id) = challenge.media_message_id {
+        match context
+            .api
+            .execute_with(
+                Priority::Low,
+                Target::chat(chat_id),
+                DeleteMessage::new(chat_id, media_message_id),
+            )
+            .await
+        {
+            Ok(_) => log::info!("Media message #{} successfully deleted", media_message_id),
+            Err(err) => log::warn!("Failed to delete media message: {}", err),
+        }
+    }
+}
+
+pub(super) fn build_keyboard(
+    config: &ChatConfig,
+    level: ChallengeLevel,
+    chat_id: Integer,
+    user_id: Option<Integer>,
+) -> Result<Vec<Vec<InlineKeyboardButton>>, HandlerError> {
+    let mut buttons = Vec::new();
+    for button in config.buttons(level) {
+        buttons.push(InlineKeyboardButton::with_callback_data_struct(
+            button.label(),
+            &Payload {
+                chat_id,
+                user_id,
+                is_right: button.is_right(),
+            },
+        )?)
+    }
+    Ok(vec![buttons])
+}
+
+async fn send_text(
+    context: &Context,
+    config: &ChatConfig,
+    chat_id: Integer,
+    reply_to: Integer,
+    text: String,
+    keyboard: Vec<Vec<InlineKeyboardButton>>,
+) -> Result<Message, HandlerError> {
+    let mut method = SendMessage::new(chat_id, text)
+        .reply_markup(keyboard)
+        .reply_to_message_id(reply_to);
+    if let Some(parse_mode) = config.parse_mode() {
+        method = method.parse_mode(parse_mode);
+    }
+    Ok(context
+        .api
+        .execute_with(Priority::Normal, Target::message(chat_id), method)
+        .await?)
+}
+
+async fn get_input_file(context: &Context, media: &MediaConfig) -> Result<InputFile, HandlerError> {
+    Ok(match media.source() {
+        MediaSource::FileId(file_id) => InputFile::file_id(file_id.as_str()),
+        MediaSource::Path(path) => {
+            let file_id = context
+                .uploaded_files
+                .lock()
+                .expect("Failed to lock uploaded files")
+                .get(path)
+                .map(|x| x.file_id.clone());
+            match file_id {
+                Some(file_id) => InputFile::file_id(file_id),
+                None => InputFile::path(path).await.map_err(HandlerError::OpenMedia)?,
+            }
+        }
+    })
+}
+
+/// Saves file_id of an uploaded file, so we don't need to upload it again, even after restart
+async fn cache_file_id(context: &Context, media: &MediaConfig, message: &Message) {
+    let path = match media.source().path() {
+        Some(path) => path,
+        None => return,
+    };
+    let file_id = match message.data {
+        MessageData::Animation(ref animation) => Some(&animation.file_id),
+        MessageData::Photo { ref data, .. } => data.last().map(|x| &x.file_id),
+        MessageData::Sticker(ref sticker) => Some(&sticker.file_id),
+        _ => None,
+    };
+    let file_id = match file_id {
+        Some(file_id) => file_id,
+        None => {
+            log::warn!("Could not find file_id for '{}' in the sent message", path.display());
+            return;
+        }
+    };
+    let is_cached = context
+        .uploaded_files
+        .lock()
+        .expect("Failed to lock uploaded files")
+        .get(path)
+        .map_or(false, |x| x.file_id == *file_id);
+    if is_cached {
+        return;
+    }
+    let file = match UploadedFile::new(path.to_owned(), file_id.clone()).await {
+        Some(file) => file,
+        None => return,
+    };
+    let files: Vec<UploadedFile> = {
+        let mut uploaded_files = context.uploaded_files.lock().expect("Failed to lock uploaded files");
+        uploaded_files.insert(path.to_owned(), file);
+        uploaded_files.values().cloned().collect()
+    };
+    if let Err(err) = context.store.save_uploaded_files(&files).await {
+        log::warn!("Failed to save uploaded files: {}", err);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{
+    api::{Priority, Target},
+    context::Context,
+    handler::{error::HandlerError, unix_time},
+    permissions::MemberStatus,
+};
+use carapax::{
+    methods::{GetChat, RestrictChatMember},
+    types::{Chat, ChatMember, ChatPermissions, Integer},
+};
+use std::time::{Duration, Instant};
+
+/// How long default permissions of a chat are cached
+const DEFAULT_PERMISSIONS_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Saves permissions of a new chat member in order to restore them after challenge
+///
+/// A status saved before is kept while it is not expired:
+/// user could leave during a challenge, so current permissions are set by us.
+/// Unless current permissions are stricter and do not look like ours, then admins restricted user meanwhile.
+pub(super) async fn remember(
+    context: &Context,
+    chat_id: Integer,
+    user_id: Integer,
+    member: &ChatMember,
+) -> Result<(), HandlerError> {
+    let saved = context
+        .store
+        .load_member_status(chat_id, user_id)
+        .await
+        .map_err(HandlerError::LoadMemberStatus)?;
+    let current = MemberStatus::from_chat_member(member);
+    match saved {
+        Some(ref status)
+            if !status.is_expired(unix_time())
+                && (current.is_challenge_restriction() || !current.is_stricter_than(status)) =>
+        {
+            log::info!(
+                "Using saved member status (chat_id={}, user_id={}): {:?}",
+                chat_id,
+                user_id,
+                status
+            );
+            Ok(())
+        }
+        _ => context
+            .store
+            .save_member_status(chat_id, user_id, &current)
+            .await
+            .map_err(HandlerError::SaveMemberStatus),
+    }
+}
+
+/// Restores permissions which user had before challenge
+///
+/// Status of a restricted user is kept until restriction expires,
+/// so it can be applied again when user rejoins.
+/// Other users get default permissions of the chat.
+pub(super) async fn restore(context: &Context, chat_id: Integer, user_id: Integer) -> Result<(), HandlerError> {
+    let saved = context
+        .store
+        .load_member_status(chat_id, user_id)
+        .await
+        .map_err(HandlerError::LoadMemberStatus)?
+        .filter(|status| !status.is_expired(unix_time()));
+    let method = match saved {
+        Some(MemberStatus::Restricted {
+            permissions,
+            until_date,
+        }) => {
+            let method = RestrictChatMember::new(chat_id, user_id).with_permissions(permissions);
+            if until_date == 0 {
+                method
+            } else {
+                method.until_date(until_date)
+            }
+        }
+        _ => {
+            context
+                .store
+                .remove_member_status(chat_id, user_id)
+                .await
+                .map_err(HandlerError::RemoveMemberStatus)?;
+            let permissions = get_default_permissions(context, chat_id).await?;
+            RestrictChatMember::new(chat_id, user_id).with_permissions(permissions)
+        }
+    };
+    context
+        .api
+        .execute_with(Priority::High, Target::member(chat_id, user_id), method)
+        .await?;
+    Ok(())
+}
+
+async fn get_default_permissions(context: &Context, chat_id: Integer) -> Result<ChatPermissions, HandlerError> {
+    let cached = context
+        .default_permissions
+        .lock()
+        .expect("Failed to lock default permissions")
+        .get(&chat_id)
+        .filter(|(obtained_at, _)| obtained_at.elapsed() < DEFAULT_PERMISSIONS_LIFETIME)
+        .map(|(_, permissions)| permissions.clone());
+    if let Some(permissions) = cached {
+        return Ok(permissions);
+    }
+    let permissions = match context
+        .api
+        .execute_with(Priority::Normal, Target::chat(chat_id), GetChat::new(chat_id))
+        .await?
+    {
+        Chat::Group(chat) => chat.permissions,
+        Chat::Supergroup(chat) => chat.permissions,
+        Chat::Channel(_) | Chat::Private(_) => None,
+    };
+    let permissions = match permissions {
+        Some(permissions) => permissions,
+        None => {
+            log::warn!("Default permissions are not available for chat {}", chat_id);
+            ChatPermissions::allowed()
+        }
+    };
+    context
+        .default_permissions
+        .lock()
+        .expect("Failed to lock default permissions")
+        .insert(chat_id, (Instant::now(), permissions.clone()));
+    Ok(permissions)
+}
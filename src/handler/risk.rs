@@ -0,0 +1,81 @@
+use crate::{
+    api::{Priority, Target},
+    config::RiskConfig,
+    context::Context,
+};
+use carapax::{methods::GetUserProfilePhotos, types::User};
+use std::fmt;
+
+/// A risk score of a new chat member
+pub(super) struct RiskScore {
+    /// Weights of signals found
+    parts: Vec<(&'static str, u64)>,
+}
+
+impl RiskScore {
+    pub(super) fn total(&self) -> u64 {
+        self.parts.iter().map(|(_, weight)| weight).sum()
+    }
+}
+
+impl fmt::Display for RiskScore {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        if self.parts.is_empty() {
+            return write!(out, "no signals");
+        }
+        for (idx, (name, weight)) in self.parts.iter().enumerate() {
+            if idx > 0 {
+                write!(out, ", ")?;
+            }
+            write!(out, "{}={}", name, weight)?;
+        }
+        Ok(())
+    }
+}
+
+/// Calculates a risk score of a new chat member
+///
+/// `is_flagged` - whether a name matches a rule with `flag` action;
+/// `is_raid` - whether user joined during a raid.
+pub(super) async fn score(
+    context: &Context,
+    config: &RiskConfig,
+    user: &User,
+    is_flagged: bool,
+    is_raid: bool,
+) -> RiskScore {
+    let mut parts = Vec::new();
+    if is_flagged {
+        parts.push(("name_rule", config.name_rule()));
+    }
+    if user.username.is_none() {
+        parts.push(("no_username", config.no_username()));
+    }
+    // Skip API request when signal is not used
+    if config.no_photo() > 0 {
+        match context
+            .api
+            .execute_with(
+                Priority::Normal,
+                Target::user(user.id),
+                GetUserProfilePhotos::new(user.id).limit(1),
+            )
+            .await
+        {
+            Ok(photos) if photos.total_count == 0 => parts.push(("no_photo", config.no_photo())),
+            Ok(_) => {}
+            Err(err) => log::warn!("Failed to get profile photos of user {}: {}", user.id, err),
+        }
+    }
+    if config.is_new_account(user.id) {
+        parts.push(("new_account", config.new_account()));
+    }
+    if config.is_language_mismatch(user.language_code.as_deref()) {
+        parts.push(("language", config.language()));
+    }
+    if is_raid {
+        parts.push(("raid", config.raid()));
+    }
+    parts.retain(|(_, weight)| *weight > 0);
+    RiskScore { parts }
+}
@@ -0,0 +1,47 @@
+use carapax::types::Integer;
+use std::{
+    collections::{HashMap, VecDeque},
+    iter,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Keeps recent joins of chats in order to detect raids
+#[derive(Clone, Default)]
+pub struct JoinTracker {
+    joins: Arc<Mutex<HashMap<Integer, VecDeque<Instant>>>>,
+}
+
+impl JoinTracker {
+    /// Records new joins and returns a number of joins within period including new ones
+    ///
+    /// Joins older than period are forgotten.
+    pub fn record(&self, chat_id: Integer, count: usize, period: Duration) -> usize {
+        let now = Instant::now();
+        let mut joins = self.joins.lock().expect("Failed to lock joins");
+        let chat_joins = joins.entry(chat_id).or_default();
+        while chat_joins.front().map_or(false, |x| now.duration_since(*x) > period) {
+            chat_joins.pop_front();
+        }
+        chat_joins.extend(iter::repeat(now).take(count));
+        chat_joins.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn record() {
+        let tracker = JoinTracker::default();
+        let period = Duration::from_millis(50);
+        assert_eq!(tracker.record(1, 2, period), 2);
+        assert_eq!(tracker.record(1, 1, period), 3);
+        assert_eq!(tracker.record(2, 1, period), 1);
+        thread::sleep(period * 2);
+        assert_eq!(tracker.record(1, 1, period), 1);
+        assert_eq!(tracker.record(1, 0, period), 1);
+    }
+}
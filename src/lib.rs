@@ -1,6 +1,13 @@
+mod api;
 pub mod app;
-
+mod batch;
+mod blocklist;
+mod challenge;
 mod config;
 mod context;
 mod handler;
+mod joins;
+mod lockdown;
 mod permissions;
+mod scheduler;
+mod store;
@@ -0,0 +1,72 @@
+use carapax::types::Integer;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A lockdown of a chat during a raid
+#[derive(Clone, Debug, Default)]
+pub struct Lockdown {
+    /// Title of the chat, in order to render the summary
+    pub chat_title: Option<String>,
+    /// Username of the chat, in order to render the summary
+    pub chat_username: Option<String>,
+    /// ID of a message announcing the lockdown
+    pub message_id: Option<Integer>,
+    /// Number of users joined since lockdown started
+    pub joins: usize,
+}
+
+/// Keeps chats in lockdown mode
+#[derive(Clone, Default)]
+pub struct LockdownRegistry {
+    chats: Arc<Mutex<HashMap<Integer, Lockdown>>>,
+}
+
+impl LockdownRegistry {
+    /// Starts a lockdown
+    ///
+    /// Returns false if chat is in lockdown already.
+    pub fn start(&self, chat_id: Integer, chat_title: Option<&str>, chat_username: Option<&str>) -> bool {
+        match self.lock().entry(chat_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(Lockdown {
+                    chat_title: chat_title.map(String::from),
+                    chat_username: chat_username.map(String::from),
+                    ..Lockdown::default()
+                });
+                true
+            }
+        }
+    }
+
+    /// Adds joins to an active lockdown
+    ///
+    /// Returns false if chat is not in lockdown.
+    pub fn add_joins(&self, chat_id: Integer, count: usize) -> bool {
+        match self.lock().get_mut(&chat_id) {
+            Some(lockdown) => {
+                lockdown.joins += count;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers a message announcing the lockdown
+    pub fn set_message(&self, chat_id: Integer, message_id: Integer) {
+        if let Some(lockdown) = self.lock().get_mut(&chat_id) {
+            lockdown.message_id = Some(message_id);
+        }
+    }
+
+    /// Ends a lockdown and returns its state
+    pub fn end(&self, chat_id: Integer) -> Option<Lockdown> {
+        self.lock().remove(&chat_id)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Integer, Lockdown>> {
+        self.chats.lock().expect("Failed to lock lockdowns")
+    }
+}
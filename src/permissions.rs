@@ -1,22 +1,148 @@
-use carapax::types::{ChatMember, ChatPermissions};
-
-/// A key to store old chat member permissions in session
-pub const PERMISSIONS_SESSION_KEY: &str = "permissions";
-
-pub fn obtain_chat_member_permissions(member: ChatMember) -> ChatPermissions {
-    use self::ChatMember::*;
-    match member {
-        Administrator(_) | Creator(_) | Left(_) | Member(_) => ChatPermissions::allowed(),
-        Kicked(_) => ChatPermissions::restricted(),
-        Restricted(restricted) => ChatPermissions {
-            can_send_messages: Some(restricted.can_send_messages),
-            can_send_media_messages: Some(restricted.can_send_media_messages),
-            can_send_polls: Some(restricted.can_send_polls),
-            can_send_other_messages: Some(restricted.can_send_other_messages),
-            can_add_web_page_previews: Some(restricted.can_add_web_page_previews),
-            can_change_info: Some(restricted.can_change_info),
-            can_invite_users: Some(restricted.can_invite_users),
-            can_pin_messages: restricted.can_pin_messages,
-        },
+use carapax::types::{ChatMember, ChatPermissions, Integer};
+use serde::{Deserialize, Serialize};
+
+/// Permissions of a chat member before a challenge started
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum MemberStatus {
+    /// User is not restricted
+    Unrestricted,
+    /// User is restricted by admins
+    Restricted {
+        permissions: ChatPermissions,
+        /// Unix time when restriction will be lifted, 0 means forever
+        until_date: Integer,
+    },
+}
+
+impl MemberStatus {
+    pub fn from_chat_member(member: &ChatMember) -> Self {
+        use self::ChatMember::*;
+        match member {
+            Administrator(_) | Creator(_) | Left(_) | Member(_) => MemberStatus::Unrestricted,
+            Kicked(kicked) => MemberStatus::Restricted {
+                permissions: ChatPermissions::restricted(),
+                until_date: kicked.until_date,
+            },
+            Restricted(restricted) => MemberStatus::Restricted {
+                permissions: ChatPermissions {
+                    can_send_messages: Some(restricted.can_send_messages),
+                    can_send_media_messages: Some(restricted.can_send_media_messages),
+                    can_send_polls: Some(restricted.can_send_polls),
+                    can_send_other_messages: Some(restricted.can_send_other_messages),
+                    can_add_web_page_previews: Some(restricted.can_add_web_page_previews),
+                    can_change_info: Some(restricted.can_change_info),
+                    can_invite_users: Some(restricted.can_invite_users),
+                    can_pin_messages: restricted.can_pin_messages,
+                },
+                until_date: restricted.until_date,
+            },
+        }
+    }
+
+    /// Whether a restriction is lifted by telegram already
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self {
+            MemberStatus::Unrestricted => false,
+            MemberStatus::Restricted { until_date, .. } => *until_date != 0 && *until_date as u64 <= now,
+        }
+    }
+
+    /// Whether status looks like a restriction applied during a challenge
+    ///
+    /// Everything is forbidden forever, so it is not distinguishable from a full mute set by admins.
+    pub fn is_challenge_restriction(&self) -> bool {
+        match self {
+            MemberStatus::Unrestricted => false,
+            MemberStatus::Restricted {
+                permissions,
+                until_date,
+            } => *until_date == 0 && count_allowed(permissions) == 0,
+        }
+    }
+
+    /// Whether status allows less than other one
+    pub fn is_stricter_than(&self, other: &MemberStatus) -> bool {
+        match (self, other) {
+            (MemberStatus::Unrestricted, _) => false,
+            (MemberStatus::Restricted { .. }, MemberStatus::Unrestricted) => true,
+            (
+                MemberStatus::Restricted {
+                    permissions,
+                    until_date,
+                },
+                MemberStatus::Restricted {
+                    permissions: other_permissions,
+                    until_date: other_until_date,
+                },
+            ) => {
+                let (allowed, other_allowed) = (count_allowed(permissions), count_allowed(other_permissions));
+                allowed < other_allowed
+                    || (allowed == other_allowed
+                        && *other_until_date != 0
+                        && (*until_date == 0 || until_date > other_until_date))
+            }
+        }
+    }
+}
+
+fn count_allowed(permissions: &ChatPermissions) -> usize {
+    [
+        permissions.can_send_messages,
+        permissions.can_send_media_messages,
+        permissions.can_send_polls,
+        permissions.can_send_other_messages,
+        permissions.can_add_web_page_previews,
+        permissions.can_change_info,
+        permissions.can_invite_users,
+        permissions.can_pin_messages,
+    ]
+    .iter()
+    .filter(|x| **x == Some(true))
+    .count()
+}
+
+/// Whether a chat member is an administrator or creator
+pub fn is_admin(member: &ChatMember) -> bool {
+    matches!(member, ChatMember::Administrator(_) | ChatMember::Creator(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restricted(permissions: ChatPermissions, until_date: Integer) -> MemberStatus {
+        MemberStatus::Restricted {
+            permissions,
+            until_date,
+        }
+    }
+
+    fn can_send_messages() -> ChatPermissions {
+        ChatPermissions {
+            can_send_messages: Some(true),
+            ..ChatPermissions::restricted()
+        }
+    }
+
+    #[test]
+    fn challenge_restriction() {
+        assert!(restricted(ChatPermissions::restricted(), 0).is_challenge_restriction());
+        assert!(!restricted(ChatPermissions::restricted(), 100).is_challenge_restriction());
+        assert!(!restricted(can_send_messages(), 0).is_challenge_restriction());
+        assert!(!MemberStatus::Unrestricted.is_challenge_restriction());
+    }
+
+    #[test]
+    fn stricter() {
+        let unrestricted = MemberStatus::Unrestricted;
+        assert!(restricted(can_send_messages(), 100).is_stricter_than(&unrestricted));
+        assert!(!unrestricted.is_stricter_than(&restricted(can_send_messages(), 100)));
+        assert!(!unrestricted.is_stricter_than(&MemberStatus::Unrestricted));
+        assert!(restricted(ChatPermissions::restricted(), 100).is_stricter_than(&restricted(can_send_messages(), 0)));
+        assert!(!restricted(can_send_messages(), 0).is_stricter_than(&restricted(ChatPermissions::restricted(), 100)));
+        assert!(restricted(can_send_messages(), 200).is_stricter_than(&restricted(can_send_messages(), 100)));
+        assert!(restricted(can_send_messages(), 0).is_stricter_than(&restricted(can_send_messages(), 100)));
+        assert!(!restricted(can_send_messages(), 100).is_stricter_than(&restricted(can_send_messages(), 0)));
+        assert!(!restricted(can_send_messages(), 100).is_stricter_than(&restricted(can_send_messages(), 100)));
     }
 }
@@ -0,0 +1,339 @@
+use crate::{
+    config::ChallengeLevel,
+    store::{Store, StoreError, StoredUser},
+};
+use carapax::types::Integer;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task,
+    time::delay_until,
+};
+
+/// A job which is running, number of the run makes it distinct from a job with the same key
+type RunningJob = (JobKey, u64);
+
+/// A delayed action
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Job {
+    /// Ask a new chat member
+    SendQuestion(QuestionRequest),
+    /// Ask new chat members by a single question
+    SendBatch {
+        chat_id: Integer,
+        /// Makes a key of the job unique, see BatchQueue
+        #[serde(default)]
+        batch_id: Integer,
+        requests: Vec<QuestionRequest>,
+    },
+    /// Update remaining time in the question
+    UpdateQuestion {
+        chat_id: Integer,
+        user_id: Integer,
+    },
+    /// Apply timeout action when user did not answer
+    ExpireChallenge {
+        chat_id: Integer,
+        user_id: Integer,
+    },
+    /// Restore permissions of a restricted user
+    LiftMute {
+        chat_id: Integer,
+        user_id: Integer,
+    },
+    DeleteMessage {
+        chat_id: Integer,
+        message_id: Integer,
+    },
+    /// End a lockdown when no users joined within quiet period
+    EndLockdown {
+        chat_id: Integer,
+    },
+    /// Remove leave history of a user who did not leave again within rejoin period
+    ForgetLeaves {
+        chat_id: Integer,
+        user_id: Integer,
+    },
+}
+
+impl Job {
+    /// Returns a key of the job
+    ///
+    /// There can be only one scheduled job with the same key.
+    pub fn key(&self) -> JobKey {
+        match *self {
+            Job::SendQuestion(ref request) => JobKey::send_question(request.chat_id, request.user.id),
+            Job::SendBatch { chat_id, batch_id, .. } => JobKey::send_batch(chat_id, batch_id),
+            Job::UpdateQuestion { chat_id, user_id } => JobKey::update_question(chat_id, user_id),
+            Job::ExpireChallenge { chat_id, user_id } => JobKey::expire_challenge(chat_id, user_id),
+            Job::LiftMute { chat_id, user_id } => JobKey::lift_mute(chat_id, user_id),
+            Job::DeleteMessage { chat_id, message_id } => JobKey::delete_message(chat_id, message_id),
+            Job::EndLockdown { chat_id } => JobKey::end_lockdown(chat_id),
+            Job::ForgetLeaves { chat_id, user_id } => JobKey::forget_leaves(chat_id, user_id),
+        }
+    }
+}
+
+/// Data required to send a question
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QuestionRequest {
+    pub chat_id: Integer,
+    pub chat_title: Option<String>,
+    pub chat_username: Option<String>,
+    pub user: StoredUser,
+    /// ID of a new chat members message
+    pub message_id: Integer,
+    /// Unix time when user joined the chat
+    pub joined_at: Integer,
+    #[serde(default)]
+    pub level: ChallengeLevel,
+    /// Number of times the question was postponed because of too many pending questions
+    #[serde(default)]
+    pub overflows: u32,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct JobKey(String);
+
+impl JobKey {
+    pub fn send_question(chat_id: Integer, user_id: Integer) -> Self {
+        Self(format!("send_question_{}_{}", chat_id, user_id))
+    }
+
+    pub fn send_batch(chat_id: Integer, batch_id: Integer) -> Self {
+        Self(format!("send_batch_{}_{}", chat_id, batch_id))
+    }
+
+    pub fn update_question(chat_id: Integer, user_id: Integer) -> Self {
+        Self(format!("update_question_{}_{}", chat_id, user_id))
+    }
+
+    pub fn expire_challenge(chat_id: Integer, user_id: Integer) -> Self {
+        Self(format!("expire_challenge_{}_{}", chat_id, user_id))
+    }
+
+    pub fn lift_mute(chat_id: Integer, user_id: Integer) -> Self {
+        Self(format!("lift_mute_{}_{}", chat_id, user_id))
+    }
+
+    pub fn delete_message(chat_id: Integer, message_id: Integer) -> Self {
+        Self(format!("delete_message_{}_{}", chat_id, message_id))
+    }
+
+    pub fn end_lockdown(chat_id: Integer) -> Self {
+        Self(format!("end_lockdown_{}", chat_id))
+    }
+
+    pub fn forget_leaves(chat_id: Integer, user_id: Integer) -> Self {
+        Self(format!("forget_leaves_{}_{}", chat_id, user_id))
+    }
+}
+
+impl fmt::Display for JobKey {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(out, "{}", self.0)
+    }
+}
+
+/// A job with execution time, as it is saved in store
+#[derive(Deserialize, Serialize)]
+struct ScheduledJob {
+    /// Unix time in milliseconds
+    run_at: u64,
+    job: Job,
+}
+
+enum Command {
+    Schedule(ScheduledJob),
+    Cancel(JobKey),
+}
+
+/// A handle to schedule and cancel jobs
+#[derive(Clone)]
+pub struct Scheduler {
+    sender: UnboundedSender<Command>,
+}
+
+impl Scheduler {
+    pub fn new(store: Store) -> (Self, SchedulerRunner) {
+        let (sender, receiver) = unbounded_channel();
+        let (finished_sender, finished_receiver) = unbounded_channel();
+        (
+            Self { sender },
+            SchedulerRunner {
+                receiver,
+                finished_sender,
+                finished_receiver,
+                store,
+                queue: BTreeMap::new(),
+                jobs: HashMap::new(),
+                running: HashMap::new(),
+                counter: 0,
+            },
+        )
+    }
+
+    /// Schedules a job to run after given delay
+    ///
+    /// Replaces a job with the same key
+    pub fn schedule(&self, delay: Duration, job: Job) {
+        let run_at = unix_time_millis() + delay.as_millis() as u64;
+        self.send(Command::Schedule(ScheduledJob { run_at, job }));
+    }
+
+    /// Cancels a job, does nothing if job is not scheduled
+    pub fn cancel(&self, key: JobKey) {
+        self.send(Command::Cancel(key));
+    }
+
+    fn send(&self, command: Command) {
+        if self.sender.send(command).is_err() {
+            log::error!("Scheduler is not running");
+        }
+    }
+}
+
+/// Keeps scheduled jobs and runs them in time
+pub struct SchedulerRunner {
+    receiver: UnboundedReceiver<Command>,
+    finished_sender: UnboundedSender<RunningJob>,
+    finished_receiver: UnboundedReceiver<RunningJob>,
+    store: Store,
+    /// Keys of jobs ordered by time
+    queue: BTreeMap<(u64, u64), JobKey>,
+    jobs: HashMap<JobKey, ((u64, u64), Job)>,
+    /// Number of the last run by job key
+    running: HashMap<JobKey, u64>,
+    /// Makes queue keys unique when jobs have the same time
+    counter: u64,
+}
+
+impl SchedulerRunner {
+    /// Runs scheduler until all handles are dropped
+    ///
+    /// Jobs saved in store are loaded first, overdue jobs run immediately.
+    /// A job is removed from store only when it is finished,
+    /// so it runs again after restart if process stopped in the middle.
+    pub async fn run<F, R>(mut self, execute: F)
+    where
+        F: Fn(Job) -> R,
+        R: Future<Output = ()> + Send + 'static,
+    {
+        match self.store.load_jobs::<ScheduledJob>().await {
+            Ok(jobs) => {
+                log::info!("Loaded {} scheduled job(s)", jobs.len());
+                for job in jobs {
+                    self.insert(job);
+                }
+            }
+            Err(err) => log::error!("Failed to load scheduled jobs: {}", err),
+        }
+        loop {
+            let now = unix_time_millis();
+            while let Some((run, job)) = self.pop_due(now) {
+                let key = job.key();
+                log::debug!("Running job {}", key);
+                self.running.insert(key.clone(), run);
+                let finished_sender = self.finished_sender.clone();
+                let job = execute(job);
+                task::spawn(async move {
+                    job.await;
+                    // Receiver lives as long as the runner
+                    let _ = finished_sender.send((key, run));
+                });
+            }
+            let deadline = self
+                .queue
+                .keys()
+                .next()
+                .map(|(run_at, _)| Instant::now() + Duration::from_millis(run_at.saturating_sub(now)));
+            let command = select! {
+                command = self.receiver.recv() => command,
+                Some((key, run)) = self.finished_receiver.recv() => {
+                    self.finish(key, run).await;
+                    continue;
+                }
+                _ = delay_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => continue,
+            };
+            match command {
+                Some(Command::Schedule(job)) => {
+                    let key = job.job.key();
+                    if let Err(err) = self.store.save_job(&key.0, &job).await {
+                        log::error!("Failed to save job {}: {}", key, err);
+                    }
+                    self.insert(job);
+                }
+                Some(Command::Cancel(key)) => {
+                    if let Some((queue_key, _)) = self.jobs.remove(&key) {
+                        self.queue.remove(&queue_key);
+                        self.remove_from_store(&key).await;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn insert(&mut self, job: ScheduledJob) {
+        let key = job.job.key();
+        self.counter += 1;
+        let queue_key = (job.run_at, self.counter);
+        if let Some((old_queue_key, _)) = self.jobs.insert(key.clone(), (queue_key, job.job)) {
+            self.queue.remove(&old_queue_key);
+        }
+        self.queue.insert(queue_key, key);
+    }
+
+    /// Returns a due job with a number of its run
+    fn pop_due(&mut self, now: u64) -> Option<(u64, Job)> {
+        let queue_key = match self.queue.keys().next() {
+            Some(queue_key) if queue_key.0 <= now => *queue_key,
+            _ => return None,
+        };
+        let key = self.queue.remove(&queue_key)?;
+        self.jobs.remove(&key).map(|(_, job)| (queue_key.1, job))
+    }
+
+    /// Removes a finished job from store
+    ///
+    /// Job is kept when it was scheduled again or started again with the same key.
+    async fn finish(&mut self, key: JobKey, run: u64) {
+        if self.running.get(&key) != Some(&run) {
+            return;
+        }
+        self.running.remove(&key);
+        if !self.jobs.contains_key(&key) {
+            self.remove_from_store(&key).await;
+        }
+    }
+
+    async fn remove_from_store(&self, key: &JobKey) {
+        if let Err(err) = self.store.remove_job(&key.0).await {
+            log::error!("Failed to remove job {}: {}", key, err);
+        }
+    }
+}
+
+/// Returns jobs saved in store
+pub async fn load_jobs(store: &Store) -> Result<Vec<Job>, StoreError> {
+    Ok(store
+        .load_jobs::<ScheduledJob>()
+        .await?
+        .into_iter()
+        .map(|x| x.job)
+        .collect())
+}
+
+fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
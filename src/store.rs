@@ -0,0 +1,380 @@
+use crate::{config::ChallengeLevel, permissions::MemberStatus};
+use carapax::types::{Integer, User};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Error as JsonError;
+use std::{
+    error::Error,
+    fmt,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::fs;
+
+const BANS_DIRECTORY: &str = "bans";
+const CHALLENGES_DIRECTORY: &str = "challenges";
+const JOBS_DIRECTORY: &str = "jobs";
+const LEAVES_DIRECTORY: &str = "leaves";
+const MEDIA_DIRECTORY: &str = "media";
+const MEMBERS_DIRECTORY: &str = "members";
+const TRUSTED_DIRECTORY: &str = "trusted";
+
+/// A name of a file with all uploaded media files, there are only a few of them
+const UPLOADED_FILES_NAME: &str = "uploaded";
+
+/// A filesystem store for data which must survive restarts
+#[derive(Clone)]
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    /// Opens a store, directories will be created if they do not exist
+    pub async fn open<P: AsRef<Path>>(root: P) -> Result<Self, StoreError> {
+        let root = root.as_ref().to_owned();
+        for name in &[
+            BANS_DIRECTORY,
+            CHALLENGES_DIRECTORY,
+            JOBS_DIRECTORY,
+            LEAVES_DIRECTORY,
+            MEDIA_DIRECTORY,
+            MEMBERS_DIRECTORY,
+            TRUSTED_DIRECTORY,
+        ] {
+            let directory = root.join(name);
+            fs::create_dir_all(&directory)
+                .await
+                .map_err(|err| StoreError::Io(directory, err))?;
+        }
+        Ok(Self { root })
+    }
+
+    pub async fn save_challenge(&self, challenge: &PendingChallenge) -> Result<(), StoreError> {
+        let name = get_challenge_name(challenge.chat_id, challenge.user.id);
+        self.save(CHALLENGES_DIRECTORY, &name, challenge).await
+    }
+
+    pub async fn load_challenge(
+        &self,
+        chat_id: Integer,
+        user_id: Integer,
+    ) -> Result<Option<PendingChallenge>, StoreError> {
+        self.load(CHALLENGES_DIRECTORY, &get_challenge_name(chat_id, user_id))
+            .await
+    }
+
+    pub async fn remove_challenge(&self, chat_id: Integer, user_id: Integer) -> Result<(), StoreError> {
+        self.remove(CHALLENGES_DIRECTORY, &get_challenge_name(chat_id, user_id))
+            .await
+    }
+
+    /// Returns unix times when user left a chat before answering
+    pub async fn load_leaves(&self, chat_id: Integer, user_id: Integer) -> Result<Vec<u64>, StoreError> {
+        self.load(LEAVES_DIRECTORY, &get_challenge_name(chat_id, user_id))
+            .await
+            .map(Option::unwrap_or_default)
+    }
+
+    pub async fn save_leaves(&self, chat_id: Integer, user_id: Integer, leaves: &[u64]) -> Result<(), StoreError> {
+        self.save(LEAVES_DIRECTORY, &get_challenge_name(chat_id, user_id), &leaves)
+            .await
+    }
+
+    pub async fn remove_leaves(&self, chat_id: Integer, user_id: Integer) -> Result<(), StoreError> {
+        self.remove(LEAVES_DIRECTORY, &get_challenge_name(chat_id, user_id))
+            .await
+    }
+
+    /// Returns permissions user had before challenge
+    pub async fn load_member_status(
+        &self,
+        chat_id: Integer,
+        user_id: Integer,
+    ) -> Result<Option<MemberStatus>, StoreError> {
+        self.load(MEMBERS_DIRECTORY, &get_challenge_name(chat_id, user_id))
+            .await
+    }
+
+    pub async fn save_member_status(
+        &self,
+        chat_id: Integer,
+        user_id: Integer,
+        status: &MemberStatus,
+    ) -> Result<(), StoreError> {
+        self.save(MEMBERS_DIRECTORY, &get_challenge_name(chat_id, user_id), status)
+            .await
+    }
+
+    pub async fn remove_member_status(&self, chat_id: Integer, user_id: Integer) -> Result<(), StoreError> {
+        self.remove(MEMBERS_DIRECTORY, &get_challenge_name(chat_id, user_id))
+            .await
+    }
+
+    /// Returns unix time when user passed a question in any chat of the network
+    pub async fn load_trusted(&self, network: &str, user_id: Integer) -> Result<Option<u64>, StoreError> {
+        self.load(TRUSTED_DIRECTORY, &get_network_name(network, user_id)).await
+    }
+
+    /// Marks user as trusted in the network, unix time of the mark is saved
+    pub async fn add_trusted(&self, network: &str, user_id: Integer, time: u64) -> Result<(), StoreError> {
+        self.save(TRUSTED_DIRECTORY, &get_network_name(network, user_id), &time)
+            .await
+    }
+
+    pub async fn remove_trusted(&self, network: &str, user_id: Integer) -> Result<(), StoreError> {
+        self.remove(TRUSTED_DIRECTORY, &get_network_name(network, user_id))
+            .await
+    }
+
+    /// Returns a ban of the user in the network
+    pub async fn load_ban(&self, network: &str, user_id: Integer) -> Result<Option<FederatedBan>, StoreError> {
+        self.load(BANS_DIRECTORY, &get_network_name(network, user_id)).await
+    }
+
+    pub async fn save_ban(&self, network: &str, user_id: Integer, ban: &FederatedBan) -> Result<(), StoreError> {
+        self.save(BANS_DIRECTORY, &get_network_name(network, user_id), ban)
+            .await
+    }
+
+    pub async fn remove_ban(&self, network: &str, user_id: Integer) -> Result<(), StoreError> {
+        self.remove(BANS_DIRECTORY, &get_network_name(network, user_id)).await
+    }
+
+    pub async fn save_job<T: Serialize>(&self, key: &str, job: &T) -> Result<(), StoreError> {
+        self.save(JOBS_DIRECTORY, key, job).await
+    }
+
+    pub async fn remove_job(&self, key: &str) -> Result<(), StoreError> {
+        self.remove(JOBS_DIRECTORY, key).await
+    }
+
+    /// Returns media files uploaded to telegram servers
+    pub async fn load_uploaded_files(&self) -> Result<Vec<UploadedFile>, StoreError> {
+        self.load(MEDIA_DIRECTORY, UPLOADED_FILES_NAME)
+            .await
+            .map(Option::unwrap_or_default)
+    }
+
+    pub async fn save_uploaded_files(&self, files: &[UploadedFile]) -> Result<(), StoreError> {
+        self.save(MEDIA_DIRECTORY, UPLOADED_FILES_NAME, &files).await
+    }
+
+    pub async fn load_challenges(&self) -> Result<Vec<PendingChallenge>, StoreError> {
+        self.load_all(CHALLENGES_DIRECTORY).await
+    }
+
+    pub async fn load_jobs<T: DeserializeOwned>(&self) -> Result<Vec<T>, StoreError> {
+        self.load_all(JOBS_DIRECTORY).await
+    }
+
+    /// Loads all files from a directory, broken files are skipped
+    async fn load_all<T: DeserializeOwned>(&self, directory: &str) -> Result<Vec<T>, StoreError> {
+        let directory = self.root.join(directory);
+        let mut entries = fs::read_dir(&directory)
+            .await
+            .map_err(|err| StoreError::Io(directory.clone(), err))?;
+        let mut result = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|err| StoreError::Io(directory.clone(), err))?
+        {
+            let path = entry.path();
+            if path.extension().and_then(|x| x.to_str()) != Some("json") {
+                continue;
+            }
+            let data = fs::read(&path).await.map_err(|err| StoreError::Io(path.clone(), err))?;
+            match serde_json::from_slice(&data) {
+                Ok(value) => result.push(value),
+                Err(err) => log::warn!("Skipping broken file '{}': {}", path.display(), err),
+            }
+        }
+        Ok(result)
+    }
+
+    async fn save<T: Serialize>(&self, directory: &str, name: &str, value: &T) -> Result<(), StoreError> {
+        let path = self.get_path(directory, name);
+        let data = serde_json::to_vec(value).map_err(StoreError::Serialize)?;
+        // Write data to a temporary file first, so a file is never left half-written
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .await
+            .map_err(|err| StoreError::Io(tmp_path.clone(), err))?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|err| StoreError::Io(path, err))
+    }
+
+    async fn load<T: DeserializeOwned>(&self, directory: &str, name: &str) -> Result<Option<T>, StoreError> {
+        let path = self.get_path(directory, name);
+        let data = match fs::read(&path).await {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == IoErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(StoreError::Io(path, err)),
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| StoreError::Deserialize(path, err))
+    }
+
+    async fn remove(&self, directory: &str, name: &str) -> Result<(), StoreError> {
+        let path = self.get_path(directory, name);
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(ref err) if err.kind() == IoErrorKind::NotFound => Ok(()),
+            Err(err) => Err(StoreError::Io(path, err)),
+        }
+    }
+
+    fn get_path(&self, directory: &str, name: &str) -> PathBuf {
+        self.root.join(directory).join(format!("{}.json", name))
+    }
+}
+
+fn get_challenge_name(chat_id: Integer, user_id: Integer) -> String {
+    format!("{}_{}", chat_id, user_id)
+}
+
+fn get_network_name(network: &str, user_id: Integer) -> String {
+    format!("{}_{}", network, user_id)
+}
+
+/// A local media file uploaded to telegram servers
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UploadedFile {
+    pub path: PathBuf,
+    pub file_id: String,
+    /// Unix time when the file was modified before upload
+    pub modified_at: u64,
+}
+
+impl UploadedFile {
+    /// Returns None when the file is not available anymore
+    pub async fn new(path: PathBuf, file_id: String) -> Option<Self> {
+        let modified_at = get_modified_time(&path).await?;
+        Some(Self {
+            path,
+            file_id,
+            modified_at,
+        })
+    }
+
+    /// Whether the file was not changed after upload, otherwise it must be uploaded again
+    pub async fn is_actual(&self) -> bool {
+        get_modified_time(&self.path).await == Some(self.modified_at)
+    }
+}
+
+async fn get_modified_time(path: &Path) -> Option<u64> {
+    let metadata = fs::metadata(path).await.ok()?;
+    let modified = metadata.modified().ok()?;
+    modified.duration_since(UNIX_EPOCH).ok().map(|x| x.as_secs())
+}
+
+/// A ban applied in all chats of a network
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FederatedBan {
+    /// A chat where user was banned
+    pub chat_id: Integer,
+    pub reason: String,
+    /// Unix time of the ban
+    pub banned_at: u64,
+}
+
+impl FederatedBan {
+    /// Whether a ban is lifted already
+    ///
+    /// Lifetime is taken from network config, so it applies to existing bans too.
+    pub fn is_expired(&self, ban_for: Option<Duration>, now: u64) -> bool {
+        ban_for.map_or(false, |ban_for| self.banned_at + ban_for.as_secs() <= now)
+    }
+}
+
+/// A question waiting for an answer
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingChallenge {
+    pub chat_id: Integer,
+    pub chat_title: Option<String>,
+    pub chat_username: Option<String>,
+    pub user: StoredUser,
+    /// Unix time when user joined the chat
+    pub joined_at: Integer,
+    #[serde(default)]
+    pub level: ChallengeLevel,
+    /// ID of a message with buttons, None while question is being sent
+    pub message_id: Option<Integer>,
+    pub media_message_id: Option<Integer>,
+    /// Whether question text is a caption of a media message
+    pub is_caption: bool,
+    /// Unix time when question expires
+    pub expires_at: u64,
+    /// Other users asked by the same question
+    #[serde(default)]
+    pub batch: Vec<Integer>,
+}
+
+/// A copy of telegram user which can be stored
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredUser {
+    pub id: Integer,
+    pub is_bot: bool,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub language_code: Option<String>,
+}
+
+impl From<&User> for StoredUser {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id,
+            is_bot: user.is_bot,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            username: user.username.clone(),
+            language_code: user.language_code.clone(),
+        }
+    }
+}
+
+impl From<StoredUser> for User {
+    fn from(user: StoredUser) -> Self {
+        Self {
+            id: user.id,
+            is_bot: user.is_bot,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            username: user.username,
+            language_code: user.language_code,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Deserialize(PathBuf, JsonError),
+    Io(PathBuf, IoError),
+    Serialize(JsonError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::StoreError::*;
+        match self {
+            Deserialize(path, err) => write!(out, "can not deserialize '{}': {}", path.display(), err),
+            Io(path, err) => write!(out, "store error at '{}': {}", path.display(), err),
+            Serialize(err) => write!(out, "can not serialize store data: {}", err),
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::StoreError::*;
+        Some(match self {
+            Deserialize(_, err) => err,
+            Io(_, err) => err,
+            Serialize(err) => err,
+        })
+    }
+}